@@ -0,0 +1,409 @@
+use crate::Event;
+use lib::Message;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+//bytes per chunk frame, small enough that chat messages never wait long behind one
+pub const CHUNK_SIZE: usize = 16 * 1024;
+//chunks read ahead of the connection, the file is streamed rather than loaded
+const READ_AHEAD: usize = 8;
+//chunks a sender runs ahead of what the recipient acknowledged, so a slow recipient slows down only its own transfer
+const WINDOW: u64 = 8;
+
+//what became of a frame from the server
+#[derive(Debug)]
+pub enum Handled {
+    //not a file frame, it goes to the frontend
+    Show(Message),
+    //a file frame that needs an answer to the server right away
+    Reply(Message),
+    Done,
+}
+
+//a file command typed by the user
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Send { to: String, path: PathBuf },
+    Accept(u64),
+    Reject(u64),
+}
+
+// the file command in a typed line, None when the line is something else
+pub fn parse_command(line: &str) -> Option<Result<Command, String>> {
+    let command = line.strip_prefix('/')?;
+    let (verb, rest) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+    let rest = rest.trim();
+
+    let parsed = match verb {
+        //the path is everything after the target, so it may contain spaces
+        "send" => match rest.split_once(char::is_whitespace) {
+            Some((to, path)) if !path.trim().is_empty() => Ok(Command::Send { to: to.to_owned(), path: PathBuf::from(path.trim()) }),
+            _ => Err("usage: /send <user|room> <path>".to_owned()),
+        },
+        "accept" => rest.parse().map(Command::Accept).map_err(|_| "usage: /accept <number>".to_owned()),
+        "reject" => rest.parse().map(Command::Reject).map_err(|_| "usage: /reject <number>".to_owned()),
+        _ => return None,
+    };
+    Some(parsed)
+}
+
+//a file we offered, kept so it can be streamed to everyone who accepts
+struct Outgoing {
+    path: PathBuf,
+    file_name: String,
+}
+
+//a file offered to us, waiting for /accept or /reject
+struct Offered {
+    id: u64,
+    from: String,
+    file_name: String,
+    size: u64,
+    sha256: String,
+}
+
+//a file being received
+struct Download {
+    file: File,
+    path: PathBuf,
+    file_name: String,
+    hasher: Sha256,
+    received: u64,
+    size: u64,
+    sha256: String,
+}
+
+// the file transfers of one session. outgoing chunks are produced by background tasks into a bounded
+// queue the session drains between chat frames, so a large file never holds up the chat
+pub struct Transfers {
+    downloads: PathBuf,
+    next_id: u64,
+    outgoing: HashMap<u64, Outgoing>,
+    //offers to us by the number the user accepts them with
+    offered: BTreeMap<u64, Offered>,
+    next_offer: u64,
+    //files being received by sender and transfer id
+    receiving: HashMap<(String, u64), Download>,
+    //how far each recipient of ours has acknowledged, by transfer id and recipient
+    windows: HashMap<(u64, String), watch::Sender<u64>>,
+    //hashing and streaming tasks
+    tasks: Vec<JoinHandle<()>>,
+    queue: mpsc::Sender<Message>,
+    //frames ready to go to the server
+    pub outbound: mpsc::Receiver<Message>,
+}
+
+impl Transfers {
+    pub fn new(downloads: PathBuf) -> Transfers {
+        let (queue, outbound) = mpsc::channel(READ_AHEAD);
+        Transfers {
+            downloads,
+            next_id: 1,
+            outgoing: HashMap::new(),
+            offered: BTreeMap::new(),
+            next_offer: 1,
+            receiving: HashMap::new(),
+            windows: HashMap::new(),
+            tasks: Vec::new(),
+            queue,
+            outbound,
+        }
+    }
+
+    // carrying out a file command, returning the frame to send if it needs one right away.
+    // offers are sent by a background task once the file is hashed
+    pub async fn command(&mut self, command: Command, events: &UnboundedSender<Event>) -> Option<Message> {
+        match command {
+            Command::Send { to, path } => {
+                let id = self.next_id;
+                self.next_id += 1;
+                let file_name = match path.file_name() {
+                    Some(name) => name.to_string_lossy().into_owned(),
+                    None => {
+                        notice(events, format!("{} is not a file", path.display()));
+                        return None;
+                    }
+                };
+
+                let (queue, events) = (self.queue.clone(), events.clone());
+                let (hashed, name) = (path.clone(), file_name.clone());
+                self.tasks.push(tokio::spawn(async move {
+                    match tokio::task::spawn_blocking(move || hash_file(&hashed)).await {
+                        Ok(Ok((size, sha256))) => {
+                            let offer = Message::FileOffer { id, from: String::new(), to, file_name: name, size, sha256 };
+                            let _ = queue.send(offer).await;
+                        }
+                        Ok(Err(e)) => notice(&events, format!("could not read {}: {}", name, e)),
+                        Err(e) => log::warn!("Hashing task failed: {}", e),
+                    }
+                }));
+                self.outgoing.insert(id, Outgoing { path, file_name });
+                None
+            }
+            Command::Accept(number) => {
+                let Some(offer) = self.offered.remove(&number) else {
+                    notice(events, format!("no file offer number {}", number));
+                    return None;
+                };
+                match self.start_download(&offer).await {
+                    Ok(path) => {
+                        notice(events, format!("receiving {} into {}", offer.file_name, path.display()));
+                        Some(Message::FileAccept { id: offer.id, from: offer.from, by: String::new() })
+                    }
+                    Err(e) => {
+                        notice(events, format!("could not save {}: {}", offer.file_name, e));
+                        Some(Message::FileReject { id: offer.id, from: offer.from, by: String::new() })
+                    }
+                }
+            }
+            Command::Reject(number) => match self.offered.remove(&number) {
+                Some(offer) => Some(Message::FileReject { id: offer.id, from: offer.from, by: String::new() }),
+                None => {
+                    notice(events, format!("no file offer number {}", number));
+                    None
+                }
+            },
+        }
+    }
+
+    // taking care of a file frame from the server, anything else is handed back for the frontend
+    pub async fn handle(&mut self, message: Message, events: &UnboundedSender<Event>) -> Handled {
+        match message {
+            Message::FileOffer { id, from, file_name, size, sha256, .. } => {
+                let number = self.next_offer;
+                self.next_offer += 1;
+                notice(
+                    events,
+                    format!("{} offers {} ({} bytes), /accept {} or /reject {}", from, file_name, size, number, number),
+                );
+                self.offered.insert(number, Offered { id, from, file_name, size, sha256 });
+            }
+            Message::FileAccept { id, by, .. } => match self.outgoing.get(&id) {
+                Some(outgoing) => {
+                    notice(events, format!("{} accepted {}", by, outgoing.file_name));
+                    let (window, acked) = watch::channel(0);
+                    let stream = stream_file(id, by.clone(), outgoing.path.clone(), acked, self.queue.clone(), events.clone());
+                    self.tasks.retain(|task| !task.is_finished());
+                    self.tasks.push(tokio::spawn(stream));
+                    //transfers whose stream has ended don't need their window anymore
+                    self.windows.retain(|_, window| window.receiver_count() > 0);
+                    self.windows.insert((id, by), window);
+                }
+                None => log::debug!("Accept for unknown transfer {}", id),
+            },
+            Message::FileAck { id, by, offset, .. } => {
+                if let Some(window) = self.windows.get(&(id, by)) {
+                    window.send_replace(offset);
+                }
+            }
+            //a reject for a file we are receiving means the server stopped it
+            Message::FileReject { id, from, .. } if self.receiving.contains_key(&(from.clone(), id)) => {
+                let download = self.receiving.remove(&(from, id)).unwrap();
+                notice(events, format!("receiving {} was stopped, it could not keep up", download.file_name));
+                let _ = tokio::fs::remove_file(&download.path).await;
+            }
+            Message::FileReject { id, by, .. } => {
+                if let Some(outgoing) = self.outgoing.get(&id) {
+                    //dropping the window ends the stream if it was running
+                    match self.windows.remove(&(id, by.clone())) {
+                        Some(_) => {
+                            notice(events, format!("sending {} to {} was stopped, it could not keep up", outgoing.file_name, by))
+                        }
+                        None => notice(events, format!("{} declined {}", by, outgoing.file_name)),
+                    }
+                }
+            }
+            Message::FileChunk { id, from, offset, data, .. } => {
+                let key = (from, id);
+                let Some(download) = self.receiving.get_mut(&key) else {
+                    return Handled::Done;
+                };
+                if let Err(reason) = download.write(offset, &data).await {
+                    let download = self.receiving.remove(&key).unwrap();
+                    notice(events, format!("receiving {} failed: {}", download.file_name, reason));
+                    let _ = tokio::fs::remove_file(&download.path).await;
+                    return Handled::Done;
+                }
+                if let Some(percent) = progress(download.received - data.len() as u64, download.received, download.size) {
+                    notice(events, format!("receiving {}: {}%", download.file_name, percent));
+                }
+                let (from, id) = key;
+                return Handled::Reply(Message::FileAck { id, from, by: String::new(), offset: download.received });
+            }
+            Message::FileDone { id, from, .. } => {
+                if let Some(download) = self.receiving.remove(&(from, id)) {
+                    notice(events, download.finish().await);
+                }
+            }
+            message => return Handled::Show(message),
+        }
+        Handled::Done
+    }
+
+    // dropping every transfer, the server forgets them when the connection goes away.
+    // partial downloads are deleted
+    pub async fn cancel(&mut self, events: &UnboundedSender<Event>) {
+        //finished tasks don't count as cancelled
+        self.tasks.retain(|task| !task.is_finished());
+        let active = !self.tasks.is_empty() || !self.receiving.is_empty() || !self.offered.is_empty();
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+        while self.outbound.try_recv().is_ok() {}
+        if active {
+            notice(events, "file transfers were cancelled".to_string());
+        }
+        self.outgoing.clear();
+        self.windows.clear();
+        self.offered.clear();
+        for (_, download) in self.receiving.drain() {
+            let _ = tokio::fs::remove_file(&download.path).await;
+        }
+    }
+
+    //creating a file in the downloads directory that doesn't overwrite anything
+    async fn start_download(&mut self, offer: &Offered) -> io::Result<PathBuf> {
+        tokio::fs::create_dir_all(&self.downloads).await?;
+        //only the last part of the offered name counts, the sender doesn't get to pick the directory
+        let name = Path::new(&offer.file_name)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "download".to_string());
+
+        let mut attempt = 0;
+        let (file, path) = loop {
+            let candidate = match attempt {
+                0 => name.clone(),
+                n => format!("{}-{}", n, name),
+            };
+            let path = self.downloads.join(candidate);
+            match tokio::fs::OpenOptions::new().write(true).create_new(true).open(&path).await {
+                Ok(file) => break (file, path),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+                Err(e) => return Err(e),
+            }
+        };
+
+        let download = Download {
+            file,
+            path: path.clone(),
+            file_name: offer.file_name.clone(),
+            hasher: Sha256::new(),
+            received: 0,
+            size: offer.size,
+            sha256: offer.sha256.clone(),
+        };
+        self.receiving.insert((offer.from.clone(), offer.id), download);
+        Ok(path)
+    }
+}
+
+impl Download {
+    //appending a chunk, which has to continue exactly where the last one stopped
+    async fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), String> {
+        if offset != self.received {
+            return Err(format!("expected data at {} but got {}", self.received, offset));
+        }
+        if self.received + data.len() as u64 > self.size {
+            return Err("more data than offered".to_string());
+        }
+        self.file.write_all(data).await.map_err(|e| e.to_string())?;
+        self.hasher.update(data);
+        self.received += data.len() as u64;
+        Ok(())
+    }
+
+    //checking the whole file against the offered checksum, a mismatch is deleted
+    async fn finish(mut self) -> String {
+        let sha256 = hex::encode(self.hasher.finalize());
+        let flushed = self.file.flush().await;
+        if self.received == self.size && sha256 == self.sha256 && flushed.is_ok() {
+            return format!("saved {} to {}", self.file_name, self.path.display());
+        }
+        let _ = tokio::fs::remove_file(&self.path).await;
+        format!("{} did not arrive intact and was discarded", self.file_name)
+    }
+}
+
+//size and hex sha256 of a file, read in chunks
+fn hash_file(path: &Path) -> io::Result<(u64, String)> {
+    use std::io::Read;
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut size = 0;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok((size, hex::encode(hasher.finalize())));
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+}
+
+//reading a file into chunk frames for one recipient, waiting whenever the queue is full or the recipient
+//has fallen a window behind. the transfer stops once its window is dropped
+async fn stream_file(
+    id: u64,
+    to: String,
+    path: PathBuf,
+    mut acked: watch::Receiver<u64>,
+    queue: mpsc::Sender<Message>,
+    events: UnboundedSender<Event>,
+) {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let opened = match File::open(&path).await {
+        Ok(file) => file.metadata().await.map(|meta| (file, meta.len())),
+        Err(e) => Err(e),
+    };
+    let (mut file, size) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            notice(&events, format!("could not read {}: {}", name, e));
+            return;
+        }
+    };
+
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut offset = 0;
+    loop {
+        let read = match file.read(&mut buffer).await {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) => {
+                notice(&events, format!("could not read {}: {}", name, e));
+                return;
+            }
+        };
+        if acked.wait_for(|acked| offset < acked + WINDOW * CHUNK_SIZE as u64).await.is_err() {
+            return;
+        }
+        let chunk = Message::FileChunk { id, from: String::new(), to: to.clone(), offset, data: buffer[..read].to_vec() };
+        if queue.send(chunk).await.is_err() {
+            return;
+        }
+        offset += read as u64;
+        if let Some(percent) = progress(offset - read as u64, offset, size) {
+            notice(&events, format!("sending {} to {}: {}%", name, to, percent));
+        }
+    }
+    let _ = queue.send(Message::FileDone { id, from: String::new(), to }).await;
+}
+
+//the percentage to report when a transfer moved from `before` to `after` bytes, in steps of ten
+fn progress(before: u64, after: u64, size: u64) -> Option<u64> {
+    let step = |bytes: u64| (bytes * 10).checked_div(size).unwrap_or(10).min(10);
+    (step(after) > step(before)).then(|| step(after) * 10)
+}
+
+fn notice(events: &UnboundedSender<Event>, text: String) {
+    let _ = events.send(Event::Notice(text));
+}
@@ -0,0 +1,84 @@
+use lib::{Message, Status};
+
+pub mod files;
+pub mod session;
+pub mod ui;
+
+//what the session reports to whichever frontend is showing the chat
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    //a frame from the server
+    Received(Message),
+    //a local hint, e.g. the usage of a mistyped command
+    Notice(String),
+    //the server ended the session for good, with its reason
+    Closed(String),
+}
+
+//what the frontend passes on to the session
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    //a line the user typed
+    Line(String),
+    //the user is typing, sent every few seconds while keys are being pressed
+    Typing,
+}
+
+// a received message in a human readable form
+pub fn format_message(message: &Message) -> String {
+    match message {
+        Message::Join { name } => format!("{} has joined the chat", name),
+        Message::Chat { name, text } => format!("{}: {}", name, text),
+        Message::Leave { name } => format!("{} has left the chat", name),
+        Message::System { text } => format!("[server] {}", text),
+        Message::Direct { from, to, text } => format!("[{} -> {}] {}", from, to, text),
+        Message::Members { room, names } => format!("in {}: {}", room, names.join(", ")),
+        Message::Presence { name, status: Status::Online } => format!("{} is online", name),
+        Message::Presence { name, status: Status::Away } => format!("{} is away", name),
+        Message::Presence { name, status: Status::Offline } => format!("{} went offline", name),
+        Message::Typing { name, .. } => format!("{} is typing", name),
+        Message::Receipt { by, read: true, .. } => format!("seen by {}", by),
+        Message::Receipt { by, read: false, .. } => format!("delivered to {}", by),
+        Message::Sequenced { message, .. } => format_message(message),
+        other => format!("{:?}", other),
+    }
+}
+
+// turning a line typed by the user into the frame to send.
+// lines starting with '/' are commands, everything else is chat text
+pub fn parse_input(name: &str, line: &str) -> Result<Message, String> {
+    let Some(command) = line.strip_prefix('/') else {
+        return Ok(Message::Chat { name: name.to_owned(), text: line.to_owned() });
+    };
+
+    let mut parts = command.split_whitespace();
+    let (verb, arg) = (parts.next(), parts.next());
+
+    // for /msg everything after the user name is the message text
+    if verb == Some("msg") {
+        let rest = command["msg".len()..].trim_start();
+        return match rest.split_once(char::is_whitespace) {
+            Some((to, text)) if !text.trim().is_empty() => {
+                Ok(Message::Direct { from: name.to_owned(), to: to.to_owned(), text: text.trim().to_owned() })
+            }
+            _ => Err("usage: /msg <user> <text>".to_owned()),
+        };
+    }
+
+    match (verb, arg) {
+        (Some("join"), Some(room)) => Ok(Message::JoinRoom { room: room.to_owned() }),
+        (Some("join"), None) => Err("usage: /join <room>".to_owned()),
+        (Some("leave"), _) => Ok(Message::LeaveRoom),
+        (Some("rooms"), _) => Ok(Message::ListRooms),
+        (Some("who"), _) => Ok(Message::Who),
+        (Some("away"), _) => Ok(Message::SetStatus { status: Status::Away }),
+        (Some("back"), _) => Ok(Message::SetStatus { status: Status::Online }),
+        (Some("history"), count) => match count.map(str::parse).unwrap_or(Ok(20)) {
+            Ok(count) => Ok(Message::History { count }),
+            Err(_) => Err("usage: /history [count]".to_owned()),
+        },
+        _ => Err("commands: /join <room>, /leave, /rooms, /who, /msg <user> <text>, /history [count], /away, /back, \
+                  /send <user|room> <path>, /accept <number>, /reject <number>"
+            .to_owned()),
+    }
+}
@@ -1,103 +1,206 @@
-use std::sync::Arc;
+use std::future::Future;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use clap::{value_parser, Arg, ArgAction, Command};
+use client::session::{Backoff, Session};
+use client::{format_message, ui, Event, Input};
+use lib::{tls, Message};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader};
 use tokio::net::TcpStream;
-use tokio::io::{AsyncWriteExt,AsyncReadExt};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 
 #[tokio::main]
 async fn main() {
-    
-    // reading user's name
-    println!("Enter your name:");
-    let mut name = String::new();
-    std::io::stdin().read_line(&mut name).expect("Failed to read from stdin");
-    let name = name.trim();
 
-    // used Arc to share ownership of the name between main and the spawned tasks
-    let shared_name = Arc::new(name.to_owned());
-    println!("Welcome to the chat!!");
-
-    //connecting to server stream
-    let stream = TcpStream::connect("127.0.0.1:8080").await.unwrap();
-    let (reader, mut writer) = tokio::io::split(stream);
-
-    writer.write_all(format!("{} has joined the chat \n<END>", name).as_bytes()).await.expect("failed to send joining message");
-    
-    //reading and writing messages concurrently by spawning 
-    let reader_task = tokio::spawn({
-        async move {
-            read_messages(reader).await;
+    // every flag can also come from its environment variable, so the client can be started non-interactively
+    let matches = Command::new("chat client")
+        .about("tcp chat client")
+        .arg(
+            Arg::new("server")
+                .long("server")
+                .env("CHAT_SERVER")
+                .value_name("ADDR")
+                .help("address of the chat server")
+                .default_value("127.0.0.1:8080"),
+        )
+        .arg(
+            Arg::new("name")
+                .long("name")
+                .env("CHAT_NAME")
+                .value_name("NAME")
+                .help("user name, asked for on stdin when missing"),
+        )
+        .arg(
+            Arg::new("password")
+                .long("password")
+                .env("CHAT_PASSWORD")
+                .value_name("PASSWORD")
+                .help("account password, asked for on the terminal when missing")
+                .hide_env_values(true),
+        )
+        .arg(
+            Arg::new("register")
+                .long("register")
+                .help("create a new account instead of logging in")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("log-level")
+                .long("log-level")
+                .env("CHAT_LOG_LEVEL")
+                .value_name("FILTER")
+                .help("log filter, e.g. warn, info or debug")
+                .default_value("warn"),
+        )
+        .arg(
+            Arg::new("tls-ca")
+                .long("tls-ca")
+                .env("CHAT_TLS_CA")
+                .value_name("PATH")
+                .help("pem certificate to trust, enables tls"),
+        )
+        .arg(
+            Arg::new("tls-domain")
+                .long("tls-domain")
+                .env("CHAT_TLS_DOMAIN")
+                .value_name("NAME")
+                .help("name the server certificate is checked against")
+                .default_value("localhost"),
+        )
+        .arg(
+            Arg::new("reconnect-max-secs")
+                .long("reconnect-max-secs")
+                .env("CHAT_RECONNECT_MAX_SECS")
+                .value_name("SECS")
+                .help("longest wait between attempts to reconnect to a server that went away")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("30"),
+        )
+        .arg(
+            Arg::new("downloads")
+                .long("downloads")
+                .env("CHAT_DOWNLOADS")
+                .value_name("DIR")
+                .help("directory accepted files are saved to")
+                .value_parser(value_parser!(PathBuf))
+                .default_value("downloads"),
+        )
+        .arg(
+            Arg::new("plain")
+                .long("plain")
+                .help("print lines instead of the terminal ui, the default when not run in a terminal")
+                .action(ArgAction::SetTrue),
+        )
+        .get_matches();
+
+    env_logger::Builder::new().parse_filters(matches.get_one::<String>("log-level").unwrap()).init();
+
+    // reading user's name unless it was given up front
+    let name = match matches.get_one::<String>("name") {
+        Some(name) => name.trim().to_owned(),
+        None => {
+            println!("Enter your name:");
+            let mut name = String::new();
+            std::io::stdin().read_line(&mut name).expect("Failed to read from stdin");
+            name.trim().to_owned()
         }
-    });
-
-    let writer_task = tokio::spawn({
-        let shared_name = Arc::clone(&shared_name);
-        async move {
-            write_messages(writer, &shared_name).await;
+    };
+
+    let password = match matches.get_one::<String>("password") {
+        Some(password) => password.clone(),
+        None => rpassword::prompt_password("Enter your password: ").expect("Failed to read password"),
+    };
+
+    let plain = matches.get_flag("plain") || !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal();
+
+    // the first frame either creates the account or logs into an existing one
+    let handshake = if matches.get_flag("register") {
+        Message::Register { name, password }
+    } else {
+        Message::Login { name, password, resume: None }
+    };
+    let backoff = Backoff { max: Duration::from_secs(*matches.get_one::<u64>("reconnect-max-secs").unwrap()), ..Backoff::default() };
+    let downloads = matches.get_one::<PathBuf>("downloads").unwrap().clone();
+
+    //connecting to server stream, wrapping it in tls when a certificate to trust was given.
+    //the session connects again through the same closure whenever the server goes away
+    let server = matches.get_one::<String>("server").unwrap().clone();
+    match matches.get_one::<String>("tls-ca") {
+        Some(ca) => {
+            let connector = tls::connector(Path::new(ca)).expect("Failed to load TLS certificate");
+            let domain = tls::server_name(matches.get_one::<String>("tls-domain").unwrap()).expect("Invalid TLS domain");
+            let connect = move || {
+                let (connector, domain, server) = (connector.clone(), domain.clone(), server.clone());
+                async move { connector.connect(domain, TcpStream::connect(server).await?).await }
+            };
+            chat(connect, handshake, backoff, downloads, plain).await;
         }
-    });
-
-    //joining spawned task before main loop ends
-    tokio::try_join!(reader_task, writer_task).expect("Failed to run tasks");
+        None => chat(move || TcpStream::connect(server.clone()), handshake, backoff, downloads, plain).await,
+    }
 }
 
-async fn read_messages(mut reader: tokio::io::ReadHalf<TcpStream>) {
-
-    let delimiter = "<END>"; //defining delimeter
-    let mut buffer = Vec::new();
-    loop {
-        let mut chunk = vec![0; 1024];
-        match reader.read(&mut chunk).await {
-            Ok(0) => {
-                // Connection closed
-                break;
-            }
-            Ok(bytes_read) => {
-                //extending the buffer with the bytes that were just read from the TCP
-                buffer.extend_from_slice(&chunk[..bytes_read]); 
-
-                //The buffer.windows(delimiter.len()) part is creating an iterator over contiguous windows in the buffer that have same length as the delimiter.
-
-                // The position method return position of the first window that equals the delimiter.
-                if let Some(i) = buffer.windows(delimiter.len()).position(|window| window == delimiter.as_bytes()) {
-
-                    let message = buffer.drain(..i).collect::<Vec<_>>(); //collecting msg before delimeter
-                    buffer.drain(..delimiter.len()); // remove the delimiter from buffer
-                    print!("{}", String::from_utf8_lossy(&message));
-                }
-            }
-            Err(e) => {
-                eprintln!("Error reading from socket: {}", e);
-                break;
-            }
+// running the chat over plain or tls connections
+async fn chat<C, F, S>(connect: C, handshake: Message, backoff: Backoff, downloads: PathBuf, plain: bool)
+where
+    C: FnMut() -> F + Send + 'static,
+    F: Future<Output = io::Result<S>> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (name, session) = match Session::open(connect, handshake, backoff, downloads).await {
+        Ok(opened) => opened,
+        Err(reason) => {
+            eprintln!("Could not join the chat: {}", reason);
+            std::process::exit(1);
         }
+    };
+
+    // the network tasks and the frontend only talk through these channels
+    let (events_tx, events_rx) = mpsc::unbounded_channel();
+    let (input_tx, input_rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(session.run(input_rx, events_tx));
+
+    // the chat is over once the server ends the session or the user quits
+    let ended = if plain {
+        println!("Welcome to the chat, {}!!", name);
+        print_lines(events_rx, input_tx).await
+    } else {
+        ui::run(&name, events_rx, input_tx).await.unwrap_or_else(|e| {
+            eprintln!("Terminal error: {}", e);
+            std::process::exit(1);
+        })
+    };
+    if let Some(reason) = ended {
+        eprintln!("Disconnected by the server: {}", reason);
+        std::process::exit(1);
     }
-}
-
-
-// function for writing message on TcpStream
-async fn write_messages(mut writer: tokio::io::WriteHalf<TcpStream>, name: &Arc<String>) {
-
-    let delimiter = "<END>"; // defining delimeter
-
-    loop {
-        // getting user's message
-        let mut message = String::new();
-        std::io::stdin().read_line(&mut message).expect("Failed to read from stdin");
 
-        // formatting message and writing on stream
-        let formatted_message = format!("{}: {}", name, message);
-        let mut bytes = formatted_message.as_bytes().to_vec();
-
-        // add the delimiter to the end of the message
-        bytes.extend_from_slice(delimiter.as_bytes());
+    // stdin is read on a blocking thread that would otherwise keep the runtime alive
+    std::process::exit(0);
+}
 
-        // write the message in chunks
-        while !bytes.is_empty() {
-            let to_write = if bytes.len() > 1024 { 1024 } else { bytes.len() };
-            let chunk = bytes.drain(..to_write).collect::<Vec<_>>();
-            writer.write_all(&chunk).await.expect("Failed to write to socket");
+// line based frontend for when there is no terminal, e.g. input piped in from a script
+async fn print_lines(mut events: UnboundedReceiver<Event>, input: UnboundedSender<Input>) -> Option<String> {
+    tokio::spawn(async move {
+        let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+        while let Ok(Some(line)) = stdin.next_line().await {
+            if input.send(Input::Line(line)).is_err() {
+                return;
+            }
         }
+        // the chat goes on after stdin closes, until the server ends it
+        std::future::pending::<()>().await;
+    });
 
-        // the flush method helps to ensure that any buffered data is sent immediately.
-        writer.flush().await.expect("Failed to flush socket");
+    while let Some(event) = events.recv().await {
+        match event {
+            // only the terminal ui has a member list and a status line to keep up to date
+            Event::Received(Message::Members { .. } | Message::Typing { .. } | Message::Receipt { .. }) => {}
+            Event::Received(message) => println!("{}", format_message(&message)),
+            Event::Notice(text) => println!("{}", text),
+            Event::Closed(reason) => return Some(reason),
+        }
     }
-}
\ No newline at end of file
+    None
+}
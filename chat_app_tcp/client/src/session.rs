@@ -0,0 +1,338 @@
+use crate::files::{self, Handled, Transfers};
+use crate::{parse_input, Event, Input};
+use lib::{write_message, FrameReader, Message, Resume};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+//how long to wait between reconnect attempts, doubling after every failure
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff { initial: Duration::from_millis(500), max: Duration::from_secs(30) }
+    }
+}
+
+//why a connection attempt failed
+enum Failure {
+    //the server could not be reached or went away during the handshake, worth trying again
+    Connection(io::Error),
+    //the server refused us, trying again won't help
+    Rejected(String),
+}
+
+//how a connection ended
+enum Ended {
+    //the frontend went away, the user is done
+    Quit,
+    //the server ended the session on purpose, e.g. an admin kicked us
+    Closed(String),
+    //the connection dropped, time to reconnect
+    Lost,
+}
+
+//one logged in connection, split so reads and writes don't wait for each other
+struct Connection<S> {
+    reader: FrameReader<ReadHalf<S>>,
+    writer: WriteHalf<S>,
+}
+
+// a chat session that outlives its connections: it reconnects when the server goes away,
+// logs back in and resumes from the last message it received
+pub struct Session<C, S> {
+    connect: C,
+    handshake: Message,
+    backoff: Backoff,
+    name: String,
+    //where we are, sent as the resume token when reconnecting
+    room: String,
+    seq: u64,
+    //seq of our own latest room message, receipts for anything older are stale
+    last_own: u64,
+    //the latest message of each author we haven't told them we read yet
+    unread: HashMap<String, u64>,
+    //frames typed while disconnected, sent once the connection is back
+    queued: VecDeque<Message>,
+    connection: Option<Connection<S>>,
+    transfers: Transfers,
+}
+
+impl<C, F, S> Session<C, S>
+where
+    C: FnMut() -> F + Send,
+    F: Future<Output = io::Result<S>> + Send,
+    S: AsyncRead + AsyncWrite + Send,
+{
+    // connecting and logging in for the first time, returning the name the server accepted.
+    // unlike reconnects, nothing is retried here so a wrong password or a dead server is reported right away.
+    // accepted files are saved to `downloads`
+    pub async fn open(connect: C, handshake: Message, backoff: Backoff, downloads: PathBuf) -> Result<(String, Session<C, S>), String> {
+        let mut session = Session {
+            connect,
+            handshake,
+            backoff,
+            name: String::new(),
+            room: String::new(),
+            seq: 0,
+            last_own: 0,
+            unread: HashMap::new(),
+            queued: VecDeque::new(),
+            connection: None,
+            transfers: Transfers::new(downloads),
+        };
+        match session.connect_once().await {
+            Ok(()) => Ok((session.name.clone(), session)),
+            Err(Failure::Connection(e)) => Err(format!("failed to connect: {}", e)),
+            Err(Failure::Rejected(reason)) => Err(reason),
+        }
+    }
+
+    // passing messages between the server and the frontend until the user quits or the server ends the session.
+    // lines typed while disconnected are queued and sent after reconnecting
+    pub async fn run(mut self, mut input: UnboundedReceiver<Input>, events: UnboundedSender<Event>) {
+        let mut delay = self.backoff.initial;
+
+        loop {
+            if let Some(connection) = self.connection.take() {
+                delay = self.backoff.initial;
+                match self.serve(connection, &mut input, &events).await {
+                    Ended::Quit => return,
+                    Ended::Closed(reason) => {
+                        let _ = events.send(Event::Closed(reason));
+                        return;
+                    }
+                    Ended::Lost => {
+                        let _ = events.send(Event::Notice("connection lost".to_string()));
+                        self.transfers.cancel(&events).await;
+                    }
+                }
+            }
+
+            //waiting out the backoff, still taking whatever the user types
+            let _ = events.send(Event::Notice(format!("reconnecting in {:.1}s", delay.as_secs_f32())));
+            let sleep = tokio::time::sleep(delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    typed = input.recv() => match typed {
+                        Some(Input::Line(line)) => self.queue(&line, &events),
+                        //nobody can be told while we are disconnected
+                        Some(Input::Typing) => {}
+                        None => return,
+                    },
+                }
+            }
+            delay = (delay * 2).min(self.backoff.max);
+
+            match self.connect_once().await {
+                Ok(()) => {
+                    let _ = events.send(Event::Notice("reconnected".to_string()));
+                }
+                Err(Failure::Connection(e)) => log::debug!("Reconnect failed: {}", e),
+                Err(Failure::Rejected(reason)) => {
+                    let _ = events.send(Event::Closed(reason));
+                    return;
+                }
+            }
+        }
+    }
+
+    //connecting and logging in, keeping the connection for `run`
+    async fn connect_once(&mut self) -> Result<(), Failure> {
+        let stream = (self.connect)().await.map_err(Failure::Connection)?;
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = FrameReader::new(reader);
+
+        //reconnects pick up where the last connection stopped
+        let mut handshake = self.handshake.clone();
+        if let Message::Login { resume, .. } = &mut handshake {
+            if !self.name.is_empty() {
+                *resume = Some(Resume { room: self.room.clone(), seq: self.seq });
+            }
+        }
+
+        write_message(&mut writer, &handshake).await.map_err(Failure::Connection)?;
+        // nothing else is allowed until the server accepted the login
+        let name = match reader.read_message().await {
+            Ok(Some(Message::Authenticated { name })) => name,
+            Ok(Some(Message::Rejected { reason })) => return Err(Failure::Rejected(reason)),
+            Ok(Some(other)) => return Err(Failure::Rejected(format!("unexpected answer from server: {:?}", other))),
+            Ok(None) => return Err(Failure::Connection(io::ErrorKind::UnexpectedEof.into())),
+            Err(e) => return Err(Failure::Connection(e)),
+        };
+
+        //an account only needs registering once, later connections log into it
+        if let Message::Register { password, .. } = &self.handshake {
+            self.handshake = Message::Login { name: name.clone(), password: password.clone(), resume: None };
+        }
+
+        self.name = name;
+        self.connection = Some(Connection { reader, writer });
+        Ok(())
+    }
+
+    async fn serve(&mut self, connection: Connection<S>, input: &mut UnboundedReceiver<Input>, events: &UnboundedSender<Event>) -> Ended {
+        let Connection { mut reader, mut writer } = connection;
+
+        //sending whatever was typed while we were away
+        while let Some(frame) = self.queued.front() {
+            if write_message(&mut writer, frame).await.is_err() {
+                return Ended::Lost;
+            }
+            self.queued.pop_front();
+        }
+
+        loop {
+            tokio::select! {
+                // the frame reader takes care of message boundaries, however tcp splits the bytes
+                frame = reader.read_message() => match frame {
+                    Ok(Some(Message::Rejected { reason })) => return Ended::Closed(reason),
+                    Ok(Some(message)) => {
+                        //receipts for a message we've since followed up on don't matter anymore
+                        if matches!(message, Message::Receipt { seq, .. } if seq < self.last_own || self.last_own == 0) {
+                            continue;
+                        }
+                        let receipt = self.acknowledge(&message);
+                        let message = self.track(message);
+                        match self.transfers.handle(message, events).await {
+                            Handled::Show(message) => {
+                                // nobody is listening anymore, the frontend has quit
+                                if events.send(Event::Received(message)).is_err() {
+                                    return Ended::Quit;
+                                }
+                            }
+                            Handled::Reply(reply) => {
+                                if let Err(e) = write_message(&mut writer, &reply).await {
+                                    log::warn!("Error writing to socket: {}", e);
+                                    return Ended::Lost;
+                                }
+                            }
+                            Handled::Done => {}
+                        }
+                        if let Some(receipt) = receipt {
+                            if let Err(e) = write_message(&mut writer, &receipt).await {
+                                log::warn!("Error writing to socket: {}", e);
+                                return Ended::Lost;
+                            }
+                        }
+                    }
+                    // Connection closed
+                    Ok(None) => return Ended::Lost,
+                    Err(e) => {
+                        log::warn!("Error reading from socket: {}", e);
+                        return Ended::Lost;
+                    }
+                },
+                typed = input.recv() => {
+                    //whatever the user does, they have seen what's on screen by now
+                    for (author, seq) in self.unread.drain() {
+                        let receipt = Message::Receipt { seq, author, by: String::new(), read: true };
+                        if let Err(e) = write_message(&mut writer, &receipt).await {
+                            log::warn!("Error writing to socket: {}", e);
+                            return Ended::Lost;
+                        }
+                    }
+                    let line = match typed {
+                        Some(Input::Line(line)) => line,
+                        Some(Input::Typing) => {
+                            let typing = Message::Typing { name: String::new(), room: String::new() };
+                            if let Err(e) = write_message(&mut writer, &typing).await {
+                                log::warn!("Error writing to socket: {}", e);
+                                return Ended::Lost;
+                            }
+                            continue;
+                        }
+                        None => return Ended::Quit,
+                    };
+                    let parsed = match files::parse_command(line.trim_end()) {
+                        Some(Ok(command)) => match self.transfers.command(command, events).await {
+                            Some(frame) => Ok(frame),
+                            None => continue,
+                        },
+                        Some(Err(usage)) => Err(usage),
+                        None => parse_input(&self.name, line.trim_end()),
+                    };
+                    // every line goes out as one frame, the length prefix keeps it in one piece
+                    match parsed {
+                        Ok(frame) => {
+                            if let Err(e) = write_message(&mut writer, &frame).await {
+                                log::warn!("Error writing to socket: {}", e);
+                                self.queued.push_back(frame);
+                                return Ended::Lost;
+                            }
+                        }
+                        Err(usage) => {
+                            let _ = events.send(Event::Notice(usage));
+                        }
+                    }
+                }
+                // file chunks take turns with everything else, one frame at a time
+                Some(piece) = self.transfers.outbound.recv() => {
+                    if let Err(e) = write_message(&mut writer, &piece).await {
+                        log::warn!("Error writing to socket: {}", e);
+                        return Ended::Lost;
+                    }
+                }
+            }
+        }
+    }
+
+    //keeping a typed line for the next connection, usage hints go straight back to the frontend
+    fn queue(&mut self, line: &str, events: &UnboundedSender<Event>) {
+        if files::parse_command(line.trim_end()).is_some() {
+            let _ = events.send(Event::Notice("not connected, file transfers have to wait".to_string()));
+            return;
+        }
+        match parse_input(&self.name, line.trim_end()) {
+            Ok(frame) => {
+                self.queued.push_back(frame);
+                let _ = events.send(Event::Notice(format!("not connected, {} messages queued", self.queued.len())));
+            }
+            Err(usage) => {
+                let _ = events.send(Event::Notice(usage));
+            }
+        }
+    }
+
+    //the delivery receipt for a room message from someone else, noting it as unread until the user does something
+    fn acknowledge(&mut self, message: &Message) -> Option<Message> {
+        let Message::Sequenced { seq, message } = message else {
+            return None;
+        };
+        let Message::Chat { name, .. } = message.as_ref() else {
+            return None;
+        };
+        if *name == self.name {
+            self.last_own = self.last_own.max(*seq);
+            return None;
+        }
+        let unread = self.unread.entry(name.clone()).or_default();
+        *unread = (*unread).max(*seq);
+        Some(Message::Receipt { seq: *seq, author: name.clone(), by: String::new(), read: false })
+    }
+
+    //remembering our room and the last message seen, unwrapping sequenced messages for the frontend
+    fn track(&mut self, message: Message) -> Message {
+        match message {
+            Message::Sequenced { seq, message } => {
+                self.seq = self.seq.max(seq);
+                *message
+            }
+            Message::Members { room, names } => {
+                self.room = room.clone();
+                Message::Members { room, names }
+            }
+            message => message,
+        }
+    }
+}
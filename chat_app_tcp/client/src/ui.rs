@@ -0,0 +1,369 @@
+use crate::{format_message, Event, Input};
+use lib::{Message, Status};
+use ratatui::crossterm::event::{self, Event as TerminalEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+//width of the user list on the right
+const SIDEBAR_WIDTH: u16 = 22;
+
+//older messages are dropped once the pane holds this many
+const MAX_MESSAGES: usize = 5_000;
+
+//lines moved by page up and page down
+const PAGE: u16 = 5;
+
+//how often the others are told we are typing, and how long such a notice is shown
+const TYPING_EVERY: Duration = Duration::from_secs(3);
+const TYPING_SHOWN: Duration = Duration::from_secs(5);
+
+//colours handed out to user names, the same name always gets the same colour
+const NAME_COLORS: [Color; 6] = [Color::Cyan, Color::Green, Color::Yellow, Color::Magenta, Color::Blue, Color::LightRed];
+
+//what a key press asks the client to do
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Nothing,
+    Send(String),
+    //the user is typing, at most once every few seconds
+    Typing,
+    Quit,
+}
+
+//everything the terminal ui shows, kept apart from the terminal itself so it can be driven by tests
+pub struct App {
+    name: String,
+    room: String,
+    messages: Vec<Line<'static>>,
+    users: BTreeSet<String>,
+    //users who are away, anyone else connected is online
+    away: BTreeSet<String>,
+    //who in the room is typing, and when we last heard about it
+    typing: HashMap<String, Instant>,
+    //who got and who read our latest message in the room
+    delivered: BTreeSet<String>,
+    seen: BTreeSet<String>,
+    input: String,
+    //how many lines the message pane is scrolled up from the bottom
+    scroll: u16,
+    last_typing: Option<Instant>,
+}
+
+impl App {
+    pub fn new(name: &str) -> App {
+        App {
+            name: name.to_string(),
+            room: String::new(),
+            messages: Vec::new(),
+            users: BTreeSet::new(),
+            away: BTreeSet::new(),
+            typing: HashMap::new(),
+            delivered: BTreeSet::new(),
+            seen: BTreeSet::new(),
+            input: String::new(),
+            scroll: 0,
+            last_typing: None,
+        }
+    }
+
+    pub fn room(&self) -> &str {
+        &self.room
+    }
+
+    pub fn users(&self) -> Vec<&str> {
+        self.users.iter().map(String::as_str).collect()
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    // the line under the messages: who is typing, otherwise who has our latest message
+    pub fn status(&self) -> String {
+        let mut typing = self
+            .typing
+            .iter()
+            .filter(|(_, since)| since.elapsed() < TYPING_SHOWN)
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        typing.sort();
+        match typing.as_slice() {
+            [] => {}
+            [name] => return format!("{} is typing...", name),
+            names => return format!("{} are typing...", names.join(", ")),
+        }
+
+        let delivered = self.delivered.difference(&self.seen).map(String::as_str).collect::<Vec<_>>();
+        let mut parts = Vec::new();
+        if !self.seen.is_empty() {
+            parts.push(format!("seen by {}", self.seen.iter().map(String::as_str).collect::<Vec<_>>().join(", ")));
+        }
+        if !delivered.is_empty() {
+            parts.push(format!("delivered to {}", delivered.join(", ")));
+        }
+        parts.join(" · ")
+    }
+
+    //the message pane as plain text, one entry per message
+    pub fn messages(&self) -> Vec<String> {
+        self.messages
+            .iter()
+            .map(|line| line.spans.iter().map(|span| span.content.as_ref()).collect())
+            .collect()
+    }
+
+    pub fn handle_message(&mut self, message: &Message) {
+        let line = match message {
+            //member lists only feed the sidebar
+            Message::Members { room, names } => {
+                if *room != self.room {
+                    self.typing.clear();
+                    self.delivered.clear();
+                    self.seen.clear();
+                }
+                self.room = room.clone();
+                self.users = names.iter().cloned().collect();
+                return;
+            }
+            //presence, typing and receipts only feed the sidebar and the status line
+            Message::Presence { name, status } => {
+                if *status == Status::Away {
+                    self.away.insert(name.clone());
+                } else {
+                    self.away.remove(name);
+                }
+                if *status == Status::Offline {
+                    self.typing.remove(name);
+                }
+                return;
+            }
+            Message::Typing { name, room } => {
+                if *room == self.room {
+                    self.typing.insert(name.clone(), Instant::now());
+                }
+                return;
+            }
+            Message::Receipt { by, read, .. } => {
+                if *read {
+                    self.seen.insert(by.clone());
+                }
+                self.delivered.insert(by.clone());
+                return;
+            }
+            Message::Join { name } => {
+                self.users.insert(name.clone());
+                Line::from(vec![name_span(name), Span::raw(" has joined the chat")]).style(Style::new().fg(Color::DarkGray))
+            }
+            Message::Leave { name } => {
+                self.users.remove(name);
+                Line::from(vec![name_span(name), Span::raw(" has left the chat")]).style(Style::new().fg(Color::DarkGray))
+            }
+            Message::Chat { name, text } => {
+                //a sent message is done typing, and our own starts over without receipts
+                self.typing.remove(name);
+                if *name == self.name {
+                    self.delivered.clear();
+                    self.seen.clear();
+                }
+                Line::from(vec![name_span(name), Span::raw(": "), Span::raw(text.clone())])
+            }
+            Message::Direct { from, to, text } => Line::from(vec![
+                Span::raw("["),
+                name_span(from),
+                Span::raw(" -> "),
+                name_span(to),
+                Span::raw("] "),
+                Span::styled(text.clone(), Style::new().add_modifier(Modifier::ITALIC)),
+            ]),
+            Message::System { text } => Line::styled(format!("[server] {}", text), Style::new().fg(Color::DarkGray)),
+            other => Line::raw(format_message(other)),
+        };
+        self.push(line);
+    }
+
+    //a hint from the client itself rather than the server
+    pub fn notice(&mut self, text: &str) {
+        self.push(Line::styled(text.to_string(), Style::new().fg(Color::Yellow)));
+    }
+
+    fn push(&mut self, line: Line<'static>) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+        self.messages.push(line);
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> Action {
+        //terminals that report releases would otherwise type every character twice
+        if key.kind != KeyEventKind::Press {
+            return Action::Nothing;
+        }
+
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('c') if control => Action::Quit,
+            KeyCode::Esc => Action::Quit,
+            KeyCode::Enter => self.submit(),
+            //some terminals send ctrl-j for the enter key
+            KeyCode::Char('j') if control => self.submit(),
+            KeyCode::Char(c) if !control => {
+                self.input.push(c);
+                //commands aren't chat, nobody needs to see those being typed
+                if self.input.starts_with('/') || self.last_typing.is_some_and(|last| last.elapsed() < TYPING_EVERY) {
+                    return Action::Nothing;
+                }
+                self.last_typing = Some(Instant::now());
+                Action::Typing
+            }
+            code => {
+                match code {
+                    KeyCode::Backspace => {
+                        self.input.pop();
+                    }
+                    KeyCode::PageUp => self.scroll = self.scroll.saturating_add(PAGE),
+                    KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(PAGE),
+                    KeyCode::Up => self.scroll = self.scroll.saturating_add(1),
+                    KeyCode::Down => self.scroll = self.scroll.saturating_sub(1),
+                    _ => {}
+                }
+                Action::Nothing
+            }
+        }
+    }
+
+    //taking the typed line out of the input box
+    fn submit(&mut self) -> Action {
+        let line = std::mem::take(&mut self.input);
+        match line.trim() {
+            "" => Action::Nothing,
+            "/quit" => Action::Quit,
+            line => {
+                //jumping back to the newest messages to see our own
+                self.scroll = 0;
+                self.last_typing = None;
+                Action::Send(line.to_string())
+            }
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, status_area, input_area] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1), Constraint::Length(3)]).areas(frame.area());
+        let [messages_area, users_area] = Layout::horizontal([Constraint::Min(10), Constraint::Length(SIDEBAR_WIDTH)]).areas(main);
+
+        //scrolling so the newest message sits at the bottom, minus however far the user scrolled up
+        let messages = Paragraph::new(self.messages.clone()).wrap(Wrap { trim: false });
+        let height = messages_area.height.saturating_sub(2);
+        let rows = messages.line_count(messages_area.width.saturating_sub(2)).min(u16::MAX as usize) as u16;
+        let bottom = rows.saturating_sub(height);
+        let title = if self.room.is_empty() { " chat ".to_string() } else { format!(" {} ", self.room) };
+        let messages = messages
+            .block(Block::bordered().title(title))
+            .scroll((bottom - self.scroll.min(bottom), 0));
+        frame.render_widget(messages, messages_area);
+
+        let users = self
+            .users
+            .iter()
+            .map(|user| {
+                let span = name_span(user);
+                let span = if *user == self.name { span.patch_style(Modifier::UNDERLINED) } else { span };
+                if self.away.contains(user) {
+                    let away = Style::new().fg(Color::DarkGray);
+                    ListItem::new(Line::from(vec![span.style(away), Span::styled(" (away)", away)]))
+                } else {
+                    ListItem::new(Line::from(span))
+                }
+            })
+            .collect::<Vec<_>>();
+        let users = List::new(users).block(Block::bordered().title(format!(" users ({}) ", self.users.len())));
+        frame.render_widget(users, users_area);
+
+        let status = Paragraph::new(self.status()).style(Style::new().fg(Color::DarkGray).add_modifier(Modifier::ITALIC));
+        frame.render_widget(status, status_area);
+
+        //long input scrolls sideways so the cursor stays visible
+        let width = input_area.width.saturating_sub(3) as usize;
+        let typed = self.input.chars().count();
+        let visible = self.input.chars().skip(typed.saturating_sub(width)).collect::<String>();
+        let input = Paragraph::new(visible.as_str()).block(Block::bordered().title(" message, /quit to leave "));
+        frame.render_widget(input, input_area);
+        frame.set_cursor_position((input_area.x + 1 + visible.chars().count() as u16, input_area.y + 1));
+    }
+}
+
+//a user name in its colour
+fn name_span(name: &str) -> Span<'static> {
+    let hash = name.bytes().fold(0usize, |hash, byte| hash.wrapping_mul(31).wrapping_add(byte as usize));
+    Span::styled(name.to_string(), Style::new().fg(NAME_COLORS[hash % NAME_COLORS.len()]).add_modifier(Modifier::BOLD))
+}
+
+// running the terminal ui until the user quits or the connection closes.
+// returns the reason if the server ended the session
+pub async fn run(name: &str, events: UnboundedReceiver<Event>, input: UnboundedSender<Input>) -> io::Result<Option<String>> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, App::new(name), events, input).await;
+    ratatui::restore();
+    result
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    mut app: App,
+    mut events: UnboundedReceiver<Event>,
+    input: UnboundedSender<Input>,
+) -> io::Result<Option<String>> {
+    let mut keys = terminal_events();
+    //redrawing now and then so typing notices go away on their own
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(Event::Received(message)) => app.handle_message(&message),
+                Some(Event::Notice(text)) => app.notice(&text),
+                Some(Event::Closed(reason)) => return Ok(Some(reason)),
+                None => return Ok(None),
+            },
+            Some(key) = keys.recv() => {
+                //anything else, e.g. a resize, only needs the redraw at the top of the loop
+                if let TerminalEvent::Key(key) = key? {
+                    match app.handle_key(key) {
+                        Action::Send(line) => {
+                            //the session is only gone once it has reported why
+                            let _ = input.send(Input::Line(line));
+                        }
+                        Action::Typing => {
+                            let _ = input.send(Input::Typing);
+                        }
+                        Action::Quit => return Ok(None),
+                        Action::Nothing => {}
+                    }
+                }
+            }
+            _ = tick.tick() => {}
+        }
+    }
+}
+
+//crossterm's event::read blocks, so it runs on its own thread feeding a channel
+fn terminal_events() -> UnboundedReceiver<io::Result<TerminalEvent>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || loop {
+        let event = event::read();
+        let failed = event.is_err();
+        if tx.send(event).is_err() || failed {
+            break;
+        }
+    });
+    rx
+}
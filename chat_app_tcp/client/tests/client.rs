@@ -0,0 +1,346 @@
+use client::files::{parse_command, Command};
+use client::parse_input;
+use client::session::{Backoff, Session};
+use client::{Event, Input};
+use lib::{write_message, FrameReader, Message, Resume, Status};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{duplex, DuplexStream};
+use tokio::sync::mpsc;
+
+//quick retries so the tests don't sit through real backoff
+const BACKOFF: Backoff = Backoff { initial: Duration::from_millis(10), max: Duration::from_millis(50) };
+
+//a connect function handing out prepared streams, failing once they run out
+fn connections(streams: Vec<DuplexStream>) -> impl FnMut() -> std::future::Ready<io::Result<DuplexStream>> + Send {
+    let mut streams = VecDeque::from(streams);
+    move || std::future::ready(streams.pop_front().ok_or_else(|| io::ErrorKind::ConnectionRefused.into()))
+}
+
+//playing the server's side of one connection
+fn fake_server<F, Fut>(stream: DuplexStream, script: F) -> tokio::task::JoinHandle<()>
+where
+    F: FnOnce(FrameReader<tokio::io::ReadHalf<DuplexStream>>, tokio::io::WriteHalf<DuplexStream>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    tokio::spawn(script(FrameReader::new(reader), writer))
+}
+
+//skipping events until a notice starting with `wanted`
+async fn wait_for(screen: &mut mpsc::UnboundedReceiver<Event>, wanted: &str) -> String {
+    loop {
+        match screen.recv().await.expect("session ended") {
+            Event::Notice(text) if text.starts_with(wanted) => return text,
+            _ => {}
+        }
+    }
+}
+
+fn login(resume: Option<Resume>) -> Message {
+    Message::Login { name: "alice".to_string(), password: "secret".to_string(), resume }
+}
+
+#[test]
+fn input_lines_become_frames() {
+    let chat = Message::Chat { name: "alice".to_string(), text: "hello there".to_string() };
+    assert_eq!(parse_input("alice", "hello there"), Ok(chat));
+    assert_eq!(parse_input("alice", "/join dev"), Ok(Message::JoinRoom { room: "dev".to_string() }));
+    assert_eq!(parse_input("alice", "/leave"), Ok(Message::LeaveRoom));
+    assert_eq!(parse_input("alice", "/history"), Ok(Message::History { count: 20 }));
+    assert_eq!(parse_input("alice", "/history 5"), Ok(Message::History { count: 5 }));
+
+    let direct = Message::Direct { from: "alice".to_string(), to: "bob".to_string(), text: "see you at 5".to_string() };
+    assert_eq!(parse_input("alice", "/msg bob see you at 5"), Ok(direct));
+
+    assert!(parse_input("alice", "/msg bob").is_err());
+    assert!(parse_input("alice", "/join").is_err());
+    assert!(parse_input("alice", "/bogus").is_err());
+
+    let send = Command::Send { to: "dev".to_string(), path: PathBuf::from("my notes.txt") };
+    assert_eq!(parse_command("/send dev my notes.txt"), Some(Ok(send)));
+    assert_eq!(parse_command("/accept 2"), Some(Ok(Command::Accept(2))));
+    assert!(parse_command("/reject").unwrap().is_err());
+    assert!(parse_command("/send bob").unwrap().is_err());
+    assert_eq!(parse_command("/join dev"), None);
+
+    assert_eq!(parse_input("alice", "/away"), Ok(Message::SetStatus { status: Status::Away }));
+    assert_eq!(parse_input("alice", "/back"), Ok(Message::SetStatus { status: Status::Online }));
+}
+
+#[tokio::test]
+async fn a_refused_login_is_reported() {
+    let (client, server) = duplex(4096);
+    let server = fake_server(server, |mut reader, mut writer| async move {
+        assert_eq!(reader.read_message().await.unwrap(), Some(login(None)));
+        let reason = "invalid name or password".to_string();
+        write_message(&mut writer, &Message::Rejected { reason }).await.unwrap();
+    });
+
+    let opened = Session::open(connections(vec![client]), login(None), BACKOFF, PathBuf::from("downloads")).await;
+    assert_eq!(opened.err(), Some("invalid name or password".to_string()));
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn an_unreachable_server_is_reported() {
+    let opened = Session::open(connections(Vec::new()), login(None), BACKOFF, PathBuf::from("downloads")).await;
+    assert!(opened.err().unwrap().starts_with("failed to connect"));
+}
+
+#[tokio::test]
+async fn the_session_reconnects_and_resumes() {
+    let (first, first_server) = duplex(4096);
+    let (second, second_server) = duplex(4096);
+
+    //the first connection registers, sees one message and then dies
+    let first_server = fake_server(first_server, |mut reader, mut writer| async move {
+        let register = Message::Register { name: "alice".to_string(), password: "secret".to_string() };
+        assert_eq!(reader.read_message().await.unwrap(), Some(register));
+        write_message(&mut writer, &Message::Authenticated { name: "alice".to_string() }).await.unwrap();
+        let members = Message::Members { room: "dev".to_string(), names: vec!["alice".to_string()] };
+        write_message(&mut writer, &members).await.unwrap();
+        let chat = Message::Chat { name: "bob".to_string(), text: "hi".to_string() };
+        write_message(&mut writer, &Message::Sequenced { seq: 7, message: Box::new(chat) }).await.unwrap();
+    });
+
+    let register = Message::Register { name: "alice".to_string(), password: "secret".to_string() };
+    let (name, session) = Session::open(connections(vec![first, second]), register, BACKOFF, PathBuf::from("downloads")).await.unwrap();
+    assert_eq!(name, "alice");
+    first_server.await.unwrap();
+
+    let (lines, input) = mpsc::unbounded_channel();
+    let (events, mut screen) = mpsc::unbounded_channel();
+    let session = tokio::spawn(session.run(input, events));
+
+    assert!(matches!(screen.recv().await, Some(Event::Received(Message::Members { .. }))));
+    assert_eq!(
+        screen.recv().await,
+        Some(Event::Received(Message::Chat { name: "bob".to_string(), text: "hi".to_string() }))
+    );
+    assert_eq!(screen.recv().await, Some(Event::Notice("connection lost".to_string())));
+
+    //typed while disconnected, delivered after reconnecting
+    lines.send(Input::Line("are you there?".to_string())).unwrap();
+
+    //the second connection logs back in where the first one stopped and then ends the session
+    let second_server = fake_server(second_server, |mut reader, mut writer| async move {
+        let resume = Resume { room: "dev".to_string(), seq: 7 };
+        assert_eq!(reader.read_message().await.unwrap(), Some(login(Some(resume))));
+        write_message(&mut writer, &Message::Authenticated { name: "alice".to_string() }).await.unwrap();
+        let chat = Message::Chat { name: "alice".to_string(), text: "are you there?".to_string() };
+        assert_eq!(reader.read_message().await.unwrap(), Some(chat));
+        write_message(&mut writer, &Message::Rejected { reason: "kicked by an admin".to_string() }).await.unwrap();
+    });
+
+    let mut events = Vec::new();
+    while let Some(event) = screen.recv().await {
+        events.push(event);
+    }
+    assert!(events.contains(&Event::Notice("reconnected".to_string())));
+    assert_eq!(events.last(), Some(&Event::Closed("kicked by an admin".to_string())));
+
+    second_server.await.unwrap();
+    session.await.unwrap();
+    drop(lines);
+}
+
+#[tokio::test]
+async fn files_are_sent_and_received() {
+    let dir = std::env::temp_dir().join(format!("chat_client_test_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let contents: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+    let sha256 = hex::encode(Sha256::digest(&contents));
+    std::fs::write(dir.join("data.bin"), &contents).unwrap();
+
+    let (client, server) = duplex(1 << 16);
+    let expected = (contents.clone(), sha256.clone());
+    let server = fake_server(server, |mut reader, mut writer| async move {
+        let (contents, sha256) = expected;
+        reader.read_message().await.unwrap();
+        write_message(&mut writer, &Message::Authenticated { name: "alice".to_string() }).await.unwrap();
+
+        //alice's file, streamed once bob accepts
+        let offer = reader.read_message().await.unwrap().unwrap();
+        assert!(matches!(&offer, Message::FileOffer { id: 1, size: 40_000, sha256: sent, .. } if *sent == sha256));
+        write_message(&mut writer, &Message::FileAccept { id: 1, from: "alice".to_string(), by: "bob".to_string() }).await.unwrap();
+        let mut received = Vec::new();
+        loop {
+            match reader.read_message().await.unwrap().unwrap() {
+                Message::FileChunk { offset, data, to, .. } => {
+                    assert_eq!((offset, to.as_str()), (received.len() as u64, "bob"));
+                    received.extend(data);
+                    let ack = Message::FileAck { id: 1, from: "alice".to_string(), by: "bob".to_string(), offset: received.len() as u64 };
+                    write_message(&mut writer, &ack).await.unwrap();
+                }
+                Message::FileDone { .. } => break,
+                other => panic!("unexpected frame {:?}", other),
+            }
+        }
+        assert_eq!(received, contents);
+
+        //bob's copy coming back, saved once alice accepts
+        let offer = Message::FileOffer {
+            id: 9,
+            from: "bob".to_string(),
+            to: "alice".to_string(),
+            file_name: "../data.bin".to_string(),
+            size: 40_000,
+            sha256,
+        };
+        write_message(&mut writer, &offer).await.unwrap();
+        let accept = Message::FileAccept { id: 9, from: "bob".to_string(), by: String::new() };
+        assert_eq!(reader.read_message().await.unwrap(), Some(accept));
+        for (i, data) in contents.chunks(16 * 1024).enumerate() {
+            let chunk = Message::FileChunk {
+                id: 9,
+                from: "bob".to_string(),
+                to: "alice".to_string(),
+                offset: (i * 16 * 1024) as u64,
+                data: data.to_vec(),
+            };
+            write_message(&mut writer, &chunk).await.unwrap();
+        }
+        write_message(&mut writer, &Message::FileDone { id: 9, from: "bob".to_string(), to: "alice".to_string() }).await.unwrap();
+        //alice acknowledges every chunk she wrote
+        for end in [16_384, 32_768, 40_000] {
+            let ack = Message::FileAck { id: 9, from: "bob".to_string(), by: String::new(), offset: end };
+            assert_eq!(reader.read_message().await.unwrap(), Some(ack));
+        }
+        //holding the connection until the client is done
+        let _ = reader.read_message().await;
+    });
+
+    let downloads = dir.join("downloads");
+    let (_, session) = Session::open(connections(vec![client]), login(None), BACKOFF, downloads.clone()).await.unwrap();
+    let (lines, input) = mpsc::unbounded_channel();
+    let (events, mut screen) = mpsc::unbounded_channel();
+    let session = tokio::spawn(session.run(input, events));
+
+    lines.send(Input::Line(format!("/send bob {}", dir.join("data.bin").display()))).unwrap();
+    wait_for(&mut screen, "sending data.bin to bob: 100%").await;
+    wait_for(&mut screen, "bob offers ../data.bin").await;
+    lines.send(Input::Line("/accept 1".to_string())).unwrap();
+    //the offered name can't reach outside the downloads directory
+    let saved = wait_for(&mut screen, "saved").await;
+    assert!(saved.ends_with(&downloads.join("data.bin").display().to_string()));
+    assert_eq!(std::fs::read(downloads.join("data.bin")).unwrap(), contents);
+
+    drop(lines);
+    session.await.unwrap();
+    server.await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn a_sender_waits_for_its_recipient() {
+    let dir = std::env::temp_dir().join(format!("chat_client_window_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("big.bin"), vec![7; 20 * 16 * 1024]).unwrap();
+
+    let (client, server) = duplex(1 << 20);
+    let server = fake_server(server, |mut reader, mut writer| async move {
+        reader.read_message().await.unwrap();
+        write_message(&mut writer, &Message::Authenticated { name: "alice".to_string() }).await.unwrap();
+        reader.read_message().await.unwrap();
+        write_message(&mut writer, &Message::FileAccept { id: 1, from: "alice".to_string(), by: "bob".to_string() }).await.unwrap();
+
+        //eight chunks go out, then nothing until bob catches up
+        let mut offsets = Vec::new();
+        while let Ok(frame) = tokio::time::timeout(Duration::from_millis(300), reader.read_message()).await {
+            match frame.unwrap().unwrap() {
+                Message::FileChunk { offset, .. } => offsets.push(offset),
+                other => panic!("unexpected frame {:?}", other),
+            }
+        }
+        assert_eq!(offsets, (0..8).map(|i| i * 16 * 1024).collect::<Vec<u64>>());
+
+        //each acknowledged chunk lets one more through
+        let ack = Message::FileAck { id: 1, from: "alice".to_string(), by: "bob".to_string(), offset: 2 * 16 * 1024 };
+        write_message(&mut writer, &ack).await.unwrap();
+        for i in 8..10 {
+            let frame = reader.read_message().await.unwrap().unwrap();
+            assert!(matches!(frame, Message::FileChunk { offset, .. } if offset == i * 16 * 1024), "{:?}", frame);
+        }
+        assert!(tokio::time::timeout(Duration::from_millis(300), reader.read_message()).await.is_err());
+
+        //the server gave up on bob, so the rest is never read
+        let stopped = Message::FileReject { id: 1, from: "alice".to_string(), by: "bob".to_string() };
+        write_message(&mut writer, &stopped).await.unwrap();
+        let _ = reader.read_message().await;
+    });
+
+    let (_, session) = Session::open(connections(vec![client]), login(None), BACKOFF, dir.join("downloads")).await.unwrap();
+    let (lines, input) = mpsc::unbounded_channel();
+    let (events, mut screen) = mpsc::unbounded_channel();
+    let session = tokio::spawn(session.run(input, events));
+
+    lines.send(Input::Line(format!("/send bob {}", dir.join("big.bin").display()))).unwrap();
+    wait_for(&mut screen, "sending big.bin to bob was stopped").await;
+
+    drop(lines);
+    session.await.unwrap();
+    server.await.unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn messages_are_acknowledged() {
+    let (client, server) = duplex(4096);
+    let sequenced = |seq: u64, name: &str, text: &str| Message::Sequenced {
+        seq,
+        message: Box::new(Message::Chat { name: name.to_string(), text: text.to_string() }),
+    };
+    let receipt = |seq: u64, author: &str, by: &str, read: bool| Message::Receipt {
+        seq,
+        author: author.to_string(),
+        by: by.to_string(),
+        read,
+    };
+
+    let (delivered, typing_seen) = tokio::sync::oneshot::channel::<()>();
+    let server = fake_server(server, move |mut reader, mut writer| async move {
+        reader.read_message().await.unwrap();
+        write_message(&mut writer, &Message::Authenticated { name: "alice".to_string() }).await.unwrap();
+
+        //bob's message is delivered right away, and read once alice starts typing
+        write_message(&mut writer, &sequenced(5, "bob", "hi")).await.unwrap();
+        assert_eq!(reader.read_message().await.unwrap(), Some(receipt(5, "bob", "", false)));
+        delivered.send(()).unwrap();
+        assert_eq!(reader.read_message().await.unwrap(), Some(receipt(5, "bob", "", true)));
+        let typing = Message::Typing { name: String::new(), room: String::new() };
+        assert_eq!(reader.read_message().await.unwrap(), Some(typing));
+
+        //a receipt for something older than alice's latest message is dropped
+        write_message(&mut writer, &sequenced(6, "alice", "hey")).await.unwrap();
+        write_message(&mut writer, &receipt(5, "alice", "bob", true)).await.unwrap();
+        write_message(&mut writer, &receipt(6, "alice", "bob", true)).await.unwrap();
+        let _ = reader.read_message().await;
+    });
+
+    let (_, session) = Session::open(connections(vec![client]), login(None), BACKOFF, PathBuf::from("downloads")).await.unwrap();
+    let (input, typed) = mpsc::unbounded_channel();
+    let (events, mut screen) = mpsc::unbounded_channel();
+    let session = tokio::spawn(session.run(typed, events));
+
+    typing_seen.await.unwrap();
+    input.send(Input::Typing).unwrap();
+
+    let mut received = Vec::new();
+    while received.len() < 3 {
+        if let Some(Event::Received(message)) = screen.recv().await {
+            received.push(message);
+        }
+    }
+    assert_eq!(received[2], receipt(6, "alice", "bob", true));
+
+    drop(input);
+    session.await.unwrap();
+    server.await.unwrap();
+}
@@ -0,0 +1,110 @@
+use client::ui::{Action, App};
+use lib::Message;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+fn press(app: &mut App, code: KeyCode) -> Action {
+    app.handle_key(KeyEvent::new(code, KeyModifiers::NONE))
+}
+
+fn type_line(app: &mut App, line: &str) -> Action {
+    for c in line.chars() {
+        assert!(matches!(press(app, KeyCode::Char(c)), Action::Nothing | Action::Typing));
+    }
+    press(app, KeyCode::Enter)
+}
+
+#[test]
+fn sidebar_follows_the_room() {
+    let mut app = App::new("alice");
+
+    let names = vec!["alice".to_string(), "bob".to_string()];
+    app.handle_message(&Message::Members { room: "lobby".to_string(), names });
+    assert_eq!(app.room(), "lobby");
+    assert_eq!(app.users(), ["alice", "bob"]);
+
+    app.handle_message(&Message::Join { name: "carl".to_string() });
+    app.handle_message(&Message::Leave { name: "bob".to_string() });
+    assert_eq!(app.users(), ["alice", "carl"]);
+
+    //member lists update the sidebar without cluttering the messages
+    assert_eq!(app.messages(), ["carl has joined the chat", "bob has left the chat"]);
+}
+
+#[test]
+fn messages_are_shown_in_order() {
+    let mut app = App::new("alice");
+    app.handle_message(&Message::Chat { name: "bob".to_string(), text: "hi".to_string() });
+    app.handle_message(&Message::System { text: "you joined dev".to_string() });
+    app.handle_message(&Message::Direct { from: "bob".to_string(), to: "alice".to_string(), text: "psst".to_string() });
+    app.notice("usage: /join <room>");
+
+    assert_eq!(
+        app.messages(),
+        ["bob: hi", "[server] you joined dev", "[bob -> alice] psst", "usage: /join <room>"]
+    );
+}
+
+#[test]
+fn typed_lines_are_sent_on_enter() {
+    let mut app = App::new("alice");
+
+    assert_eq!(type_line(&mut app, "hello"), Action::Send("hello".to_string()));
+    assert_eq!(app.input(), "");
+
+    //blank lines go nowhere
+    assert_eq!(type_line(&mut app, "   "), Action::Nothing);
+
+    //sending a line means typing the next one is news again
+    type_line(&mut app, "typo");
+    assert_eq!(press(&mut app, KeyCode::Char('x')), Action::Typing);
+    assert_eq!(press(&mut app, KeyCode::Backspace), Action::Nothing);
+    assert_eq!(app.input(), "");
+
+    press(&mut app, KeyCode::Char('h'));
+    press(&mut app, KeyCode::Char('x'));
+    press(&mut app, KeyCode::Backspace);
+    assert_eq!(app.input(), "h");
+}
+
+#[test]
+fn quitting() {
+    let mut app = App::new("alice");
+    assert_eq!(type_line(&mut app, "/quit"), Action::Quit);
+    assert_eq!(press(&mut app, KeyCode::Esc), Action::Quit);
+    assert_eq!(app.handle_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)), Action::Quit);
+}
+
+#[test]
+fn typing_is_announced_now_and_then() {
+    let mut app = App::new("alice");
+    assert_eq!(press(&mut app, KeyCode::Char('h')), Action::Typing);
+    assert_eq!(press(&mut app, KeyCode::Char('i')), Action::Nothing);
+    assert_eq!(press(&mut app, KeyCode::Enter), Action::Send("hi".to_string()));
+
+    //commands are nobody else's business
+    assert_eq!(press(&mut app, KeyCode::Char('/')), Action::Nothing);
+}
+
+#[test]
+fn the_status_line_shows_typing_and_receipts() {
+    let mut app = App::new("alice");
+    let names = ["alice", "bob", "carl"].map(String::from).to_vec();
+    app.handle_message(&Message::Members { room: "lobby".to_string(), names });
+
+    app.handle_message(&Message::Typing { name: "bob".to_string(), room: "lobby".to_string() });
+    assert_eq!(app.status(), "bob is typing...");
+    app.handle_message(&Message::Chat { name: "bob".to_string(), text: "hi".to_string() });
+    assert_eq!(app.status(), "");
+
+    app.handle_message(&Message::Chat { name: "alice".to_string(), text: "hello".to_string() });
+    let receipt = |by: &str, read: bool| Message::Receipt { seq: 2, author: "alice".to_string(), by: by.to_string(), read };
+    app.handle_message(&receipt("bob", false));
+    app.handle_message(&receipt("carl", false));
+    app.handle_message(&receipt("bob", true));
+    assert_eq!(app.status(), "seen by bob · delivered to carl");
+
+    //the next message starts over
+    app.handle_message(&Message::Chat { name: "alice".to_string(), text: "anyone?".to_string() });
+    assert_eq!(app.status(), "");
+    assert_eq!(app.messages(), ["bob: hi", "alice: hello", "alice: anyone?"]);
+}
@@ -0,0 +1,237 @@
+use serde::{Deserialize, Serialize};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub mod tls;
+
+//upper bound for a single frame so a bad length prefix can't make us allocate gigabytes
+pub const MAX_FRAME_LEN: usize = 1024 * 1024;
+
+//size of the big-endian length prefix in front of every frame
+const LEN_PREFIX: usize = 4;
+
+//messages exchanged between chat server and clients
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    //login handshake, the first frame of every connection has to be one of these two
+    Register { name: String, password: String },
+    Login {
+        name: String,
+        password: String,
+        //set when reconnecting, so the server can pick up where the last connection left off
+        #[serde(default)]
+        resume: Option<Resume>,
+    },
+    //the server's answer to a successful register or login
+    Authenticated { name: String },
+    Join { name: String },
+    Chat { name: String, text: String },
+    Leave { name: String },
+    System { text: String },
+    //room commands sent by the client, the server answers with system messages
+    JoinRoom { room: String },
+    LeaveRoom,
+    ListRooms,
+    Who,
+    //private message, only delivered to the named user
+    Direct { from: String, to: String, text: String },
+    //asks the server for the last `count` messages of the current room
+    History { count: usize },
+    //sent by the server right before it closes a connection it won't accept
+    Rejected { reason: String },
+    //everyone currently in a room, pushed to its members whenever someone enters or leaves
+    Members { room: String, names: Vec<String> },
+    //a room message with its position in the server's history, the latest seq is the client's resume token
+    Sequenced { seq: u64, message: Box<Message> },
+    //asks a user, or everyone in a room, to accept a file. `id` is picked by the sender and identifies the transfer together with its name
+    FileOffer { id: u64, from: String, to: String, file_name: String, size: u64, sha256: String },
+    //a recipient's answer to an offer, routed back to the sender
+    FileAccept { id: u64, from: String, by: String },
+    FileReject { id: u64, from: String, by: String },
+    //a piece of an accepted file on its way from `from` to `to`
+    FileChunk {
+        id: u64,
+        from: String,
+        to: String,
+        offset: u64,
+        #[serde(with = "base64_data")]
+        data: Vec<u8>,
+    },
+    //the recipient has written everything before `offset`, routed back to the sender. a sender only runs a few chunks ahead of it
+    FileAck { id: u64, from: String, by: String, offset: u64 },
+    //the last chunk was sent, the recipient checks the file against the offer's checksum
+    FileDone { id: u64, from: String, to: String },
+    //a user's availability, pushed to everyone connected whenever it changes
+    Presence { name: String, status: Status },
+    //a client changing its own status, e.g. going away on purpose
+    SetStatus { status: Status },
+    //someone is typing in a room, sent by the client every few seconds while its user types
+    Typing { name: String, room: String },
+    //acknowledges the room message `seq` to its author: delivered once `by`'s client has it, read once `by` has seen it
+    Receipt { seq: u64, author: String, by: String, read: bool },
+}
+
+//what other users see of someone's availability
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Online,
+    //set by the user, or by the server after a while without activity
+    Away,
+    Offline,
+}
+
+//file chunks travel as base64 strings inside the json frames
+mod base64_data {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+//where a reconnecting client was: its room and the last history seq it received
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Resume {
+    pub room: String,
+    pub seq: u64,
+}
+
+//encoding a message as a length-prefixed json frame
+pub fn encode(message: &Message) -> io::Result<Vec<u8>> {
+    let body = serde_json::to_vec(message)?;
+    if body.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame too large"));
+    }
+
+    let mut frame = Vec::with_capacity(LEN_PREFIX + body.len());
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&body);
+    Ok(frame)
+}
+
+//writing a single framed message and flushing it
+pub async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &Message) -> io::Result<()> {
+    let frame = encode(message)?;
+    writer.write_all(&frame).await?;
+    writer.flush().await
+}
+
+//reads frames off a byte stream no matter how the bytes were split into segments
+pub struct FrameReader<R> {
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    pub fn new(reader: R) -> Self {
+        FrameReader { reader, buffer: Vec::new() }
+    }
+
+    //returns the next message, or None once the peer closed the connection cleanly.
+    //only `read` is awaited and bytes are buffered after it completes, so this is safe to use inside tokio::select!
+    pub async fn read_message(&mut self) -> io::Result<Option<Message>> {
+        loop {
+            if let Some(message) = self.parse_frame()? {
+                return Ok(Some(message));
+            }
+
+            let mut chunk = [0; 4096];
+            let bytes_read = self.reader.read(&mut chunk).await?;
+            if bytes_read == 0 {
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                }
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed mid-frame"));
+            }
+            self.buffer.extend_from_slice(&chunk[..bytes_read]);
+        }
+    }
+
+    //taking one complete frame out of the buffer if we have it
+    fn parse_frame(&mut self) -> io::Result<Option<Message>> {
+        if self.buffer.len() < LEN_PREFIX {
+            return Ok(None);
+        }
+
+        let mut len_bytes = [0; LEN_PREFIX];
+        len_bytes.copy_from_slice(&self.buffer[..LEN_PREFIX]);
+        let len = u32::from_be_bytes(len_bytes) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+        }
+        if self.buffer.len() < LEN_PREFIX + len {
+            return Ok(None);
+        }
+
+        let frame = self.buffer.drain(..LEN_PREFIX + len).collect::<Vec<_>>();
+        let message = serde_json::from_slice(&frame[LEN_PREFIX..])?;
+        Ok(Some(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::{duplex, DuplexStream};
+
+    fn chat(text: &str) -> Message {
+        Message::Chat { name: "alice".to_string(), text: text.to_string() }
+    }
+
+    //whether the reader is still waiting for the rest of a frame. read_message keeps what it has buffered
+    //when it is cancelled, so giving up on it here loses nothing
+    async fn still_waiting(reader: &mut FrameReader<DuplexStream>) -> bool {
+        tokio::time::timeout(Duration::from_millis(20), reader.read_message()).await.is_err()
+    }
+
+    #[tokio::test]
+    async fn a_frame_split_across_reads_is_put_back_together() {
+        let (mut peer, stream) = duplex(1024);
+        let mut reader = FrameReader::new(stream);
+        let frame = encode(&chat("hello there")).unwrap();
+
+        //every piece is in the pipe before the read it should arrive with, including one that splits the length prefix
+        let (prefix, body) = frame.split_at(2);
+        let (first, rest) = body.split_at(5);
+        for piece in [prefix, first] {
+            peer.write_all(piece).await.unwrap();
+            assert!(still_waiting(&mut reader).await);
+        }
+        peer.write_all(rest).await.unwrap();
+        assert_eq!(reader.read_message().await.unwrap(), Some(chat("hello there")));
+    }
+
+    #[tokio::test]
+    async fn two_frames_in_one_read_come_out_one_at_a_time() {
+        let (mut peer, stream) = duplex(1024);
+        let mut reader = FrameReader::new(stream);
+        let mut bytes = encode(&chat("one")).unwrap();
+        bytes.extend(encode(&chat("two")).unwrap());
+        peer.write_all(&bytes).await.unwrap();
+        drop(peer);
+
+        assert_eq!(reader.read_message().await.unwrap(), Some(chat("one")));
+        assert_eq!(reader.read_message().await.unwrap(), Some(chat("two")));
+        assert_eq!(reader.read_message().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn an_oversized_length_prefix_is_refused() {
+        let (mut peer, stream) = duplex(1024);
+        let mut reader = FrameReader::new(stream);
+
+        //the prefix alone is enough, nothing gets allocated for a body that never comes
+        peer.write_all(&(MAX_FRAME_LEN as u32 + 1).to_be_bytes()).await.unwrap();
+        let error = reader.read_message().await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,67 @@
+use lib::{tls, write_message, FrameReader, Message};
+use std::path::{Path, PathBuf};
+use tokio::net::{TcpListener, TcpStream};
+
+//writes a fresh self-signed certificate for localhost into its own temp directory
+fn self_signed(tag: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("chat_tls_{}_{}", tag, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+    std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+    std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+    (cert_path, key_path)
+}
+
+//accepts one tls connection and echoes every frame back as a system message
+async fn echo_server(cert: &Path, key: &Path) -> std::net::SocketAddr {
+    let acceptor = tls::acceptor(cert, key).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let Ok(stream) = acceptor.accept(socket).await else {
+            return;
+        };
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = FrameReader::new(reader);
+        while let Ok(Some(message)) = reader.read_message().await {
+            let echo = Message::System { text: format!("{:?}", message) };
+            write_message(&mut writer, &echo).await.unwrap();
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn frames_round_trip_over_tls() {
+    let (cert, key) = self_signed("round_trip");
+    let addr = echo_server(&cert, &key).await;
+
+    let connector = tls::connector(&cert).unwrap();
+    let socket = TcpStream::connect(addr).await.unwrap();
+    let stream = connector.connect(tls::server_name("localhost").unwrap(), socket).await.unwrap();
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = FrameReader::new(reader);
+
+    let join = Message::Join { name: "alice".to_string() };
+    write_message(&mut writer, &join).await.unwrap();
+    let reply = reader.read_message().await.unwrap().unwrap();
+    assert_eq!(reply, Message::System { text: format!("{:?}", join) });
+}
+
+#[tokio::test]
+async fn untrusted_certificate_is_refused() {
+    let (cert, key) = self_signed("server");
+    let (other_ca, _) = self_signed("other");
+    let addr = echo_server(&cert, &key).await;
+
+    //the client only trusts a different self-signed certificate
+    let connector = tls::connector(&other_ca).unwrap();
+    let socket = TcpStream::connect(addr).await.unwrap();
+    let result = connector.connect(tls::server_name("localhost").unwrap(), socket).await;
+    assert!(result.is_err());
+}
@@ -0,0 +1,160 @@
+use rand::RngCore;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+//random bytes mixed into every password hash
+const SALT_LEN: usize = 16;
+
+//stored credentials of one user
+#[derive(Clone)]
+pub struct Account {
+    salt: Vec<u8>,
+    iterations: u32,
+    hash: Vec<u8>,
+}
+
+impl Account {
+    //hashing a new password with a fresh salt. slow on purpose, call it off the async runtime
+    pub fn new(password: &str, iterations: u32) -> Account {
+        let mut salt = vec![0; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let hash = hash_password(password, &salt, iterations);
+        Account { salt, iterations, hash }
+    }
+
+    //checking a password against the stored hash. slow on purpose, call it off the async runtime
+    pub fn verify(&self, password: &str) -> bool {
+        let hash = hash_password(password, &self.salt, self.iterations);
+        //comparing every byte so the time taken doesn't leak how much of the hash matched
+        hash.len() == self.hash.len() && hash.iter().zip(&self.hash).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+}
+
+//spending as long as checking a password would, for names that have no account, so the time taken
+//doesn't tell which names are registered. slow on purpose, call it off the async runtime
+pub fn verify_unknown(password: &str, iterations: u32) -> bool {
+    std::hint::black_box(hash_password(password, &[0; SALT_LEN], iterations));
+    false
+}
+
+fn hash_password(password: &str, salt: &[u8], iterations: u32) -> Vec<u8> {
+    let mut hash = vec![0; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut hash);
+    hash
+}
+
+//user accounts kept in a local file, one "name salt iterations hash" line per user
+pub struct Accounts {
+    path: PathBuf,
+    users: HashMap<String, Account>,
+}
+
+impl Accounts {
+    //loading every account from the file, a missing file just means no accounts yet
+    pub fn open(path: &Path) -> io::Result<Accounts> {
+        let mut accounts = Accounts { path: path.to_path_buf(), users: HashMap::new() };
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(accounts),
+            Err(e) => return Err(e),
+        };
+
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match parse_line(&line) {
+                Some((name, account)) => {
+                    accounts.users.insert(name, account);
+                }
+                None => log::warn!("Skipping bad account line in {}", path.display()),
+            }
+        }
+        Ok(accounts)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Account> {
+        self.users.get(name).cloned()
+    }
+
+    //storing a new account, failing if the name is already registered
+    pub fn insert(&mut self, name: &str, account: Account) -> Result<(), String> {
+        if self.users.contains_key(name) {
+            return Err(format!("the name {} is already registered", name));
+        }
+
+        let line = format!("{} {} {} {}\n", name, hex::encode(&account.salt), account.iterations, hex::encode(&account.hash));
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+        if let Err(e) = written {
+            log::error!("Failed to save account {}: {}", name, e);
+            return Err("could not save the account".to_string());
+        }
+
+        self.users.insert(name.to_string(), account);
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(String, Account)> {
+    let mut parts = line.split(' ');
+    let name = parts.next()?.to_string();
+    let salt = hex::decode(parts.next()?).ok()?;
+    let iterations = parts.next()?.parse().ok()?;
+    let hash = hex::decode(parts.next()?).ok()?;
+    Some((name, Account { salt, iterations, hash }))
+}
+
+//failed logins per ip address, an address that fails too often is locked out for a while
+pub struct LoginLimiter {
+    max_failures: u32,
+    window: Duration,
+    failures: HashMap<IpAddr, (u32, Instant)>,
+}
+
+impl LoginLimiter {
+    pub fn new(max_failures: u32, window: Duration) -> LoginLimiter {
+        LoginLimiter { max_failures, window, failures: HashMap::new() }
+    }
+
+    //whether this address has used up its attempts for the current window
+    pub fn is_blocked(&mut self, ip: IpAddr) -> bool {
+        self.expire();
+        matches!(self.failures.get(&ip), Some((count, _)) if *count >= self.max_failures)
+    }
+
+    //taking one attempt for this address, false when it has none left. the attempt counts as a failure
+    //until record_success clears it, so attempts running at the same time can't all get past the check
+    pub fn attempt(&mut self, ip: IpAddr) -> bool {
+        if self.is_blocked(ip) {
+            return false;
+        }
+        let entry = self.failures.entry(ip).or_insert((0, Instant::now()));
+        entry.0 += 1;
+        true
+    }
+
+    pub fn record_success(&mut self, ip: IpAddr) {
+        self.failures.remove(&ip);
+    }
+
+    //handing back an attempt that went through without proving anything, like registering a new name.
+    //earlier failures stay, unlike after a successful login
+    pub fn refund(&mut self, ip: IpAddr) {
+        if let Some((count, _)) = self.failures.get_mut(&ip) {
+            *count = count.saturating_sub(1);
+        }
+    }
+
+    //forgetting failures once their window has passed, for every address so ones that never come back don't pile up
+    fn expire(&mut self) {
+        let window = self.window;
+        self.failures.retain(|_, (_, since)| since.elapsed() < window);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{broadcast, system, Control, State};
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+//reading admin commands from `input` until it closes, answering each on `output`
+pub async fn run<R, W>(state: Arc<State>, input: R, mut output: W)
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut lines = BufReader::new(input).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (command, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let arg = arg.trim();
+        let answer = match (command, arg) {
+            ("/kick", user) if !user.is_empty() => kick(&state, user).await,
+            ("/broadcast", text) if !text.is_empty() => announce(&state, text).await,
+            ("/list", _) => list(&state).await,
+            ("/ban", ip) if !ip.is_empty() => match ip.parse() {
+                Ok(ip) => ban(&state, ip).await,
+                Err(_) => format!("{} is not an ip address", ip),
+            },
+            _ => "commands: /kick <user>, /broadcast <text>, /list, /ban <ip>".to_string(),
+        };
+        if output.write_all(format!("{}\n", answer).as_bytes()).await.is_err() {
+            break;
+        }
+        let _ = output.flush().await;
+    }
+}
+
+//disconnecting one user
+async fn kick(state: &State, user: &str) -> String {
+    match state.users.lock().await.get(user) {
+        Some(handle) => {
+            let _ = handle.tx.send(Control::Kick("kicked by an admin".to_string()));
+            format!("kicked {}", user)
+        }
+        None => format!("no user named {}", user),
+    }
+}
+
+//sending a notice to every room
+async fn announce(state: &State, text: &str) -> String {
+    let rooms = state.rooms.lock().await.keys().cloned().collect::<Vec<_>>();
+    for room in &rooms {
+        broadcast(state, room, system(format!("[admin] {}", text))).await;
+    }
+    format!("sent to {} rooms", rooms.len())
+}
+
+//every connected user with their address and room
+async fn list(state: &State) -> String {
+    let users = state.users.lock().await;
+    let rooms = state.rooms.lock().await;
+
+    let mut lines = users
+        .iter()
+        .map(|(name, handle)| {
+            let room = rooms
+                .iter()
+                .find(|(_, room)| room.members.contains_key(&handle.id))
+                .map(|(room, _)| room.as_str())
+                .unwrap_or("-");
+            format!("{} {} {}", name, handle.addr, room)
+        })
+        .collect::<Vec<_>>();
+    lines.sort();
+    format!("{} connected\n{}", lines.len(), lines.join("\n"))
+}
+
+//refusing new connections from an address and dropping the ones it already has
+async fn ban(state: &State, ip: IpAddr) -> String {
+    state.banned.lock().await.insert(ip);
+
+    let mut kicked = 0;
+    for handle in state.users.lock().await.values() {
+        if handle.addr.ip() == ip {
+            let _ = handle.tx.send(Control::Kick("banned by an admin".to_string()));
+            kicked += 1;
+        }
+    }
+    format!("banned {}, disconnected {} users", ip, kicked)
+}
@@ -0,0 +1,255 @@
+use clap::{value_parser, Arg, Command};
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+//server settings, every flag can also be given through its environment variable
+pub struct Config {
+    //address the chat listener binds to
+    pub bind: String,
+    //address for websocket clients such as browsers, none without it
+    pub ws_bind: Option<String>,
+    //address of the http endpoint with /metrics and /healthz, none without it
+    pub metrics_bind: Option<String>,
+    //connections beyond this are turned away with a rejection
+    pub max_clients: usize,
+    //how long a new connection may take to log in or register, and for its tls or websocket handshake, before it is dropped
+    pub handshake_timeout_secs: u64,
+    //env_logger filter, e.g. "info" or "server=debug"
+    pub log_level: String,
+    //pem certificate chain and private key, clients have to speak tls when both are set
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    //file holding the registered accounts and their salted password hashes
+    pub accounts_file: PathBuf,
+    //pbkdf2 rounds used when hashing new passwords
+    pub hash_iterations: u32,
+    //failed logins an ip address gets within the lockout window before it is refused
+    pub max_login_failures: u32,
+    pub login_lockout_secs: u64,
+    //append-only log every broadcast message is written to
+    pub history_file: PathBuf,
+    //how many recent messages of a room are replayed when a client enters it
+    pub history_replay: usize,
+    //how many messages a room's broadcast channel buffers for its slowest member
+    pub room_capacity: usize,
+    //a client that lags this many times in a row without catching up gets disconnected
+    pub max_lag_strikes: u32,
+    //how long shutdown waits for clients to receive their last messages
+    pub drain_timeout_secs: u64,
+    //largest file a user may offer to others
+    pub max_file_size: u64,
+    //seconds without activity before a user is shown as away
+    pub idle_secs: u64,
+}
+
+impl Config {
+    //parsing the process arguments
+    pub fn load() -> Config {
+        Config::from_args(std::env::args_os())
+    }
+
+    //parsing an explicit argument list, the first item is the program name. exits with usage on bad arguments
+    pub fn from_args<I, T>(args: I) -> Config
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        Config::try_from_args(args).unwrap_or_else(|e| e.exit())
+    }
+
+    //like from_args, but handing bad arguments back instead of exiting
+    pub fn try_from_args<I, T>(args: I) -> Result<Config, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = command().try_get_matches_from(args)?;
+        Ok(Config {
+            bind: matches.get_one::<String>("bind").unwrap().clone(),
+            ws_bind: matches.get_one::<String>("ws-bind").cloned(),
+            metrics_bind: matches.get_one::<String>("metrics-bind").cloned(),
+            max_clients: *matches.get_one::<u64>("max-clients").unwrap() as usize,
+            handshake_timeout_secs: *matches.get_one("handshake-timeout-secs").unwrap(),
+            log_level: matches.get_one::<String>("log-level").unwrap().clone(),
+            tls_cert: matches.get_one::<String>("tls-cert").map(PathBuf::from),
+            tls_key: matches.get_one::<String>("tls-key").map(PathBuf::from),
+            accounts_file: matches.get_one::<String>("accounts-file").unwrap().into(),
+            hash_iterations: *matches.get_one("hash-iterations").unwrap(),
+            max_login_failures: *matches.get_one("max-login-failures").unwrap(),
+            login_lockout_secs: *matches.get_one("login-lockout-secs").unwrap(),
+            history_file: matches.get_one::<String>("history-file").unwrap().into(),
+            history_replay: *matches.get_one("history-replay").unwrap(),
+            room_capacity: *matches.get_one::<u64>("room-capacity").unwrap() as usize,
+            max_lag_strikes: *matches.get_one("max-lag-strikes").unwrap(),
+            drain_timeout_secs: *matches.get_one("drain-timeout-secs").unwrap(),
+            max_file_size: *matches.get_one("max-file-size").unwrap(),
+            idle_secs: *matches.get_one("idle-secs").unwrap(),
+        })
+    }
+}
+
+fn command() -> Command {
+    Command::new("chat server")
+        .about("tcp chat server with rooms and history")
+        .arg(
+            Arg::new("bind")
+                .long("bind")
+                .env("CHAT_BIND")
+                .value_name("ADDR")
+                .help("address to listen on")
+                .default_value("127.0.0.1:8080"),
+        )
+        .arg(
+            Arg::new("ws-bind")
+                .long("ws-bind")
+                .env("CHAT_WS_BIND")
+                .value_name("ADDR")
+                .help("address to accept websocket connections on, e.g. from browsers"),
+        )
+        .arg(
+            Arg::new("metrics-bind")
+                .long("metrics-bind")
+                .env("CHAT_METRICS_BIND")
+                .value_name("ADDR")
+                .help("address to serve prometheus metrics on /metrics and a health check on /healthz"),
+        )
+        .arg(
+            Arg::new("max-clients")
+                .long("max-clients")
+                .env("CHAT_MAX_CLIENTS")
+                .value_name("COUNT")
+                .help("maximum number of connected clients")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("1024"),
+        )
+        .arg(
+            Arg::new("handshake-timeout-secs")
+                .long("handshake-timeout-secs")
+                .env("CHAT_HANDSHAKE_TIMEOUT_SECS")
+                .value_name("SECONDS")
+                .help("how long a new connection has to log in, or finish a tls or websocket handshake, before it is dropped")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("10"),
+        )
+        .arg(
+            Arg::new("log-level")
+                .long("log-level")
+                .env("CHAT_LOG_LEVEL")
+                .value_name("FILTER")
+                .help("log filter, e.g. warn, info or debug")
+                .default_value("info"),
+        )
+        .arg(
+            Arg::new("tls-cert")
+                .long("tls-cert")
+                .env("CHAT_TLS_CERT")
+                .value_name("PATH")
+                .help("pem certificate chain, enables tls")
+                .requires("tls-key"),
+        )
+        .arg(
+            Arg::new("tls-key")
+                .long("tls-key")
+                .env("CHAT_TLS_KEY")
+                .value_name("PATH")
+                .help("pem private key for the certificate")
+                .requires("tls-cert"),
+        )
+        .arg(
+            Arg::new("accounts-file")
+                .long("accounts-file")
+                .env("CHAT_ACCOUNTS_FILE")
+                .value_name("PATH")
+                .help("file with registered accounts and salted password hashes")
+                .default_value("chat_accounts.txt"),
+        )
+        .arg(
+            Arg::new("hash-iterations")
+                .long("hash-iterations")
+                .env("CHAT_HASH_ITERATIONS")
+                .value_name("COUNT")
+                .help("pbkdf2 rounds for new password hashes")
+                .value_parser(value_parser!(u32).range(1..))
+                .default_value("100000"),
+        )
+        .arg(
+            Arg::new("max-login-failures")
+                .long("max-login-failures")
+                .env("CHAT_MAX_LOGIN_FAILURES")
+                .value_name("COUNT")
+                .help("failed logins per ip before it is locked out")
+                .value_parser(value_parser!(u32).range(1..))
+                .default_value("5"),
+        )
+        .arg(
+            Arg::new("login-lockout-secs")
+                .long("login-lockout-secs")
+                .env("CHAT_LOGIN_LOCKOUT_SECS")
+                .value_name("SECONDS")
+                .help("how long failed logins count against an ip")
+                .value_parser(value_parser!(u64))
+                .default_value("60"),
+        )
+        .arg(
+            Arg::new("history-file")
+                .long("history-file")
+                .env("CHAT_HISTORY_FILE")
+                .value_name("PATH")
+                .help("append-only log of every room message")
+                .default_value("chat_history.log"),
+        )
+        .arg(
+            Arg::new("history-replay")
+                .long("history-replay")
+                .env("CHAT_HISTORY_REPLAY")
+                .value_name("COUNT")
+                .help("messages replayed to a client entering a room")
+                .value_parser(value_parser!(usize))
+                .default_value("20"),
+        )
+        .arg(
+            Arg::new("room-capacity")
+                .long("room-capacity")
+                .env("CHAT_ROOM_CAPACITY")
+                .value_name("COUNT")
+                .help("messages buffered per room for slow clients")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("100"),
+        )
+        .arg(
+            Arg::new("max-lag-strikes")
+                .long("max-lag-strikes")
+                .env("CHAT_MAX_LAG_STRIKES")
+                .value_name("COUNT")
+                .help("disconnect a client after lagging this many times in a row")
+                .value_parser(value_parser!(u32).range(1..))
+                .default_value("3"),
+        )
+        .arg(
+            Arg::new("drain-timeout-secs")
+                .long("drain-timeout-secs")
+                .env("CHAT_DRAIN_TIMEOUT_SECS")
+                .value_name("SECONDS")
+                .help("how long shutdown waits for clients to close")
+                .value_parser(value_parser!(u64))
+                .default_value("5"),
+        )
+        .arg(
+            Arg::new("max-file-size")
+                .long("max-file-size")
+                .env("CHAT_MAX_FILE_SIZE")
+                .value_name("BYTES")
+                .help("largest file users may send each other")
+                .value_parser(value_parser!(u64))
+                .default_value("104857600"),
+        )
+        .arg(
+            Arg::new("idle-secs")
+                .long("idle-secs")
+                .env("CHAT_IDLE_SECS")
+                .value_name("SECONDS")
+                .help("how long a user can be inactive before being shown as away")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("300"),
+        )
+}
@@ -0,0 +1,156 @@
+use crate::{send_direct, system, Control, State};
+use lib::Message;
+use std::collections::{HashMap, HashSet};
+use tokio::sync::mpsc;
+
+//file chunks queued per recipient. senders keep a window of unacknowledged chunks per transfer,
+//so this only fills up when a recipient stops reading, and then its transfers are stopped
+pub const QUEUE_LEN: usize = 64;
+
+//an offered file, who it went to and who said yes
+pub struct Offer {
+    recipients: HashSet<String>,
+    accepted: HashSet<String>,
+}
+
+//open offers keyed by the sender's name and its transfer id
+pub type Offers = HashMap<(String, u64), Offer>;
+
+//passing an offer on to a user, or to everyone else in a room. returns the notice for the sender
+pub async fn offer(state: &State, from: &str, message: Message) -> Message {
+    let Message::FileOffer { id, to, file_name, size, sha256, .. } = message else {
+        return system("not a file offer".to_string());
+    };
+    if size > state.config.max_file_size {
+        return system(format!("{} is too large, the limit is {} bytes", file_name, state.config.max_file_size));
+    }
+
+    let users = state.users.lock().await;
+    //a user name wins over a room of the same name
+    let recipients = if users.contains_key(&to) {
+        vec![to.clone()]
+    } else {
+        match state.rooms.lock().await.get(&to) {
+            Some(room) => room.members.values().filter(|member| *member != from).cloned().collect(),
+            None => return system(format!("no user or room named {}", to)),
+        }
+    };
+    if recipients.is_empty() {
+        return system(format!("nobody else is in {}", to));
+    }
+
+    let offer = Message::FileOffer { id, from: from.to_string(), to: to.clone(), file_name: file_name.clone(), size, sha256 };
+    for recipient in &recipients {
+        if let Some(handle) = users.get(recipient) {
+            let _ = handle.tx.send(Control::Deliver(offer.clone()));
+        }
+    }
+    drop(users);
+
+    let recipients = recipients.into_iter().collect();
+    state.offers.lock().await.insert((from.to_string(), id), Offer { recipients, accepted: HashSet::new() });
+    system(format!("offered {} to {}", file_name, to))
+}
+
+//routing an accept or reject back to the sender, only from someone the file was offered to.
+//returns a notice for whoever answered if it went nowhere
+pub async fn answer(state: &State, by: &str, message: Message) -> Option<Message> {
+    let (id, from, accepted) = match message {
+        Message::FileAccept { id, from, .. } => (id, from, true),
+        Message::FileReject { id, from, .. } => (id, from, false),
+        _ => return None,
+    };
+
+    {
+        let mut offers = state.offers.lock().await;
+        let key = (from.clone(), id);
+        let Some(offer) = offers.get_mut(&key) else {
+            return Some(system("that file is no longer on offer".to_string()));
+        };
+        if !offer.recipients.remove(by) {
+            return Some(system("that file was not offered to you".to_string()));
+        }
+        if accepted {
+            offer.accepted.insert(by.to_string());
+        } else if offer.recipients.is_empty() && offer.accepted.is_empty() {
+            offers.remove(&key);
+        }
+    }
+
+    let answer = if accepted {
+        Message::FileAccept { id, from: from.clone(), by: by.to_string() }
+    } else {
+        Message::FileReject { id, from: from.clone(), by: by.to_string() }
+    };
+    if send_direct(state, &from, answer).await {
+        None
+    } else {
+        Some(system(format!("{} is no longer connected", from)))
+    }
+}
+
+//where a chunk or done message from `from` has to go, if its recipient accepted the transfer.
+//returns the recipient's file queue together with the message as it should be delivered
+pub async fn route(state: &State, from: &str, message: Message) -> Option<(mpsc::Sender<Message>, Message)> {
+    let (id, to, done, message) = match message {
+        Message::FileChunk { id, to, offset, data, .. } => {
+            (id, to.clone(), false, Message::FileChunk { id, from: from.to_string(), to, offset, data })
+        }
+        Message::FileDone { id, to, .. } => (id, to.clone(), true, Message::FileDone { id, from: from.to_string(), to }),
+        _ => return None,
+    };
+
+    {
+        let mut offers = state.offers.lock().await;
+        let key = (from.to_string(), id);
+        let offer = offers.get_mut(&key)?;
+        if !offer.accepted.contains(&to) {
+            return None;
+        }
+        //the transfer to this recipient is over, the offer goes once nobody is left on it
+        if done {
+            offer.accepted.remove(&to);
+            if offer.recipients.is_empty() && offer.accepted.is_empty() {
+                offers.remove(&key);
+            }
+        }
+    }
+
+    let queue = state.users.lock().await.get(&to)?.files.clone();
+    Some((queue, message))
+}
+
+//passing a recipient's progress back to the sender, so it can send more. dropped if the
+//transfer is not running
+pub async fn acknowledge(state: &State, by: &str, message: Message) {
+    let Message::FileAck { id, from, offset, .. } = message else {
+        return;
+    };
+    let running = state.offers.lock().await.get(&(from.clone(), id)).is_some_and(|offer| offer.accepted.contains(by));
+    if running {
+        send_direct(state, &from, Message::FileAck { id, from: from.clone(), by: by.to_string(), offset }).await;
+    }
+}
+
+//giving up on the transfer of `id` from `from` to `to` because its recipient fell too far behind.
+//the recipient is told to discard what it has, the returned reject is for the sender
+pub async fn stop(state: &State, from: &str, id: u64, to: &str) -> Message {
+    {
+        let mut offers = state.offers.lock().await;
+        let key = (from.to_string(), id);
+        if let Some(offer) = offers.get_mut(&key) {
+            offer.accepted.remove(to);
+            if offer.recipients.is_empty() && offer.accepted.is_empty() {
+                offers.remove(&key);
+            }
+        }
+    }
+    let reject = Message::FileReject { id, from: from.to_string(), by: to.to_string() };
+    send_direct(state, to, reject.clone()).await;
+    reject
+}
+
+//dropping every offer a user made, once it disconnects nothing more will be sent
+pub async fn forget(state: &State, from: &str) {
+    state.offers.lock().await.retain(|(sender, _), _| sender != from);
+}
@@ -0,0 +1,98 @@
+use lib::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+//how many messages per room are kept in memory for replay and /history
+const MEMORY_PER_ROOM: usize = 10_000;
+
+//one line of the history log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub seq: u64,
+    pub room: String,
+    pub message: Message,
+}
+
+//append-only chat log on disk, with the most recent messages of every room kept in memory
+pub struct History {
+    file: File,
+    next_seq: u64,
+    rooms: HashMap<String, VecDeque<Record>>,
+}
+
+impl History {
+    //opening the log file, loading whatever earlier runs wrote to it
+    pub fn open(path: &Path) -> io::Result<History> {
+        let mut history = History {
+            file: OpenOptions::new().create(true).append(true).open(path)?,
+            next_seq: 1,
+            rooms: HashMap::new(),
+        };
+
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            //a torn last line from a crash shouldn't stop the server from starting
+            match serde_json::from_str::<Record>(&line) {
+                Ok(record) => history.remember(record),
+                Err(e) => log::warn!("Skipping bad history line: {}", e),
+            }
+        }
+        Ok(history)
+    }
+
+    //numbering a message and writing it to the log. a failed write is only logged,
+    //the message still gets its seq so live delivery doesn't depend on the disk
+    pub fn append(&mut self, room: &str, message: Message) -> Record {
+        let record = Record { seq: self.next_seq, room: room.to_string(), message };
+        if let Err(e) = self.write(&record) {
+            log::error!("Failed to write history: {}", e);
+        }
+
+        self.remember(record.clone());
+        record
+    }
+
+    //the last `count` messages of a room, oldest first
+    pub fn recent(&self, room: &str, count: usize) -> Vec<Record> {
+        let Some(records) = self.rooms.get(room) else {
+            return Vec::new();
+        };
+        records.iter().skip(records.len().saturating_sub(count)).cloned().collect()
+    }
+
+    //the messages of a room after `seq`, at most the last `count` of them, oldest first
+    pub fn since(&self, room: &str, seq: u64, count: usize) -> Vec<Record> {
+        let Some(records) = self.rooms.get(room) else {
+            return Vec::new();
+        };
+        let newer = records.iter().rev().take_while(|record| record.seq > seq).take(count).count();
+        records.iter().skip(records.len() - newer).cloned().collect()
+    }
+
+    //the last `count` messages of a room before `seq`, oldest first
+    pub fn before(&self, room: &str, seq: u64, count: usize) -> Vec<Record> {
+        let Some(records) = self.rooms.get(room) else {
+            return Vec::new();
+        };
+        let older = records.iter().take_while(|record| record.seq < seq).count();
+        records.iter().take(older).skip(older.saturating_sub(count)).cloned().collect()
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.file.write_all(&line)
+    }
+
+    fn remember(&mut self, record: Record) {
+        self.next_seq = self.next_seq.max(record.seq + 1);
+        let records = self.rooms.entry(record.room.clone()).or_default();
+        if records.len() == MEMORY_PER_ROOM {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+}
@@ -0,0 +1,843 @@
+mod accounts;
+mod admin;
+pub mod config;
+mod files;
+mod history;
+mod metrics;
+mod presence;
+mod websocket;
+
+use accounts::{verify_unknown, Account, Accounts, LoginLimiter};
+pub use config::Config;
+use files::Offers;
+use history::{History, Record};
+use metrics::{Counted, Metrics};
+use lib::{tls, write_message, FrameReader, Message, Resume, Status};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc, watch, Mutex, Semaphore};
+use tokio::task::JoinSet;
+
+//room every client lands in after joining, and goes back to on /leave
+const DEFAULT_ROOM: &str = "lobby";
+
+//upper bound for a single /history request
+const MAX_HISTORY_REQUEST: usize = 500;
+
+//a named room with its own broadcast channel and the clients currently in it
+struct Room {
+    tx: broadcast::Sender<Record>,
+    members: HashMap<u64, String>,
+}
+
+//what other tasks can ask of a client's connection
+enum Control {
+    //a private message to write to the client
+    Deliver(Message),
+    //closing the connection, telling the client why
+    Kick(String),
+}
+
+//a logged in user, reachable from other connections and the admin console
+struct UserHandle {
+    id: u64,
+    addr: SocketAddr,
+    tx: mpsc::UnboundedSender<Control>,
+    //file chunks for this user, bounded so a recipient that stops reading can't pile them up
+    files: mpsc::Sender<Message>,
+    status: Status,
+}
+
+//state shared by all client tasks
+struct State {
+    config: Config,
+    //rooms are created on demand and removed as soon as the last member leaves
+    rooms: Mutex<HashMap<String, Room>>,
+    //logged in usernames mapped to their connection, used for private messages and admin commands
+    users: Mutex<HashMap<String, UserHandle>>,
+    //log of everything broadcast to rooms, replayed to clients entering a room
+    history: Mutex<History>,
+    //one permit per allowed connection
+    slots: Semaphore,
+    //registered users and their password hashes
+    accounts: Mutex<Accounts>,
+    //failed login attempts per ip address
+    login_limiter: Mutex<LoginLimiter>,
+    //addresses banned from the admin console
+    banned: Mutex<HashSet<IpAddr>>,
+    //files offered by users, with who may receive them
+    offers: Mutex<Offers>,
+    //flips to true once the server starts shutting down
+    shutdown: watch::Receiver<bool>,
+    //counters for the metrics endpoint
+    metrics: Arc<Metrics>,
+}
+
+//a configured chat server, ready to serve connections from a listener
+pub struct Server {
+    state: Arc<State>,
+    acceptor: Option<tls::TlsAcceptor>,
+    shutdown_tx: watch::Sender<bool>,
+    //second listener for websocket clients, served next to the tcp one
+    websocket: Option<TcpListener>,
+    //http listener for /metrics and /healthz
+    metrics: Option<TcpListener>,
+}
+
+impl Server {
+    //loading the certificate, history and accounts up front so bad paths fail before we start listening
+    pub fn new(config: Config) -> io::Result<Server> {
+        let acceptor = match (&config.tls_cert, &config.tls_key) {
+            (Some(cert), Some(key)) => Some(tls::acceptor(cert, key)?),
+            _ => None,
+        };
+        let history = History::open(&config.history_file)?;
+        let accounts = Accounts::open(&config.accounts_file)?;
+        let login_limiter = LoginLimiter::new(config.max_login_failures, Duration::from_secs(config.login_lockout_secs));
+
+        let (shutdown_tx, shutdown) = watch::channel(false);
+
+        //shared rooms, users and history, every client task holds a handle to it
+        let state = Arc::new(State {
+            banned: Mutex::new(HashSet::new()),
+            offers: Mutex::new(Offers::new()),
+            shutdown,
+            metrics: Arc::new(Metrics::default()),
+            slots: Semaphore::new(config.max_clients),
+            accounts: Mutex::new(accounts),
+            login_limiter: Mutex::new(login_limiter),
+            config,
+            rooms: Mutex::new(HashMap::new()),
+            users: Mutex::new(HashMap::new()),
+            history: Mutex::new(history),
+        });
+        Ok(Server { state, acceptor, shutdown_tx, websocket: None, metrics: None })
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.acceptor.is_some()
+    }
+
+    //also accepting websocket clients from `listener`, they join the same rooms as tcp clients.
+    //with tls configured they have to connect with wss
+    pub fn listen_websocket(&mut self, listener: TcpListener) {
+        self.websocket = Some(listener);
+    }
+
+    //answering http requests for /metrics and /healthz on `listener`
+    pub fn listen_metrics(&mut self, listener: TcpListener) {
+        self.metrics = Some(listener);
+    }
+
+    //taking admin commands from the process's stdin
+    pub fn spawn_admin_console(&self) {
+        self.spawn_admin(tokio::io::stdin(), tokio::io::stdout());
+    }
+
+    //taking admin commands from any stream, each answered with a line on `output`
+    pub fn spawn_admin<R, W>(&self, input: R, output: W)
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        tokio::spawn(admin::run(self.state.clone(), input, output));
+    }
+
+    //accepting clients from `listener` until `shutdown` resolves, then closing every connection cleanly
+    pub async fn run(self, listener: TcpListener, shutdown: impl Future<Output = ()>) {
+        let Server { state, acceptor, shutdown_tx, websocket, metrics } = self;
+        let scraping = metrics.map(|listener| tokio::spawn(metrics::serve(listener, state.clone())));
+        let mut rate = tokio::time::interval(Duration::from_secs(1));
+
+        //every connection gets an id so rooms can track their members
+        let next_id = AtomicU64::new(1);
+
+        //client tasks are kept so shutdown can wait for them to finish
+        let mut clients = JoinSet::new();
+        tokio::pin!(shutdown);
+
+        //accepting connection from different clients
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (socket, addr) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            log::warn!("Failed to accept connection: {}", e);
+                            continue;
+                        }
+                    };
+                    let state = state.clone();
+                    let acceptor = acceptor.clone();
+                    let id = next_id.fetch_add(1, Ordering::Relaxed);
+
+                    // sspawning task for each connected client, the tls handshake happens inside it so a slow peer can't block accept
+                    clients.spawn(async move {
+                        match acceptor {
+                            Some(acceptor) => {
+                                if let Some(stream) = within_handshake(&state, addr, "TLS", acceptor.accept(socket)).await {
+                                    handle_client(stream, addr, id, state).await
+                                }
+                            }
+                            None => handle_client(socket, addr, id, state).await,
+                        }
+                    });
+                }
+                accepted = accept_websocket(websocket.as_ref()) => {
+                    let (socket, addr) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            log::warn!("Failed to accept websocket connection: {}", e);
+                            continue;
+                        }
+                    };
+                    let state = state.clone();
+                    let acceptor = acceptor.clone();
+                    let id = next_id.fetch_add(1, Ordering::Relaxed);
+
+                    clients.spawn(async move {
+                        match acceptor {
+                            Some(acceptor) => {
+                                if let Some(stream) = within_handshake(&state, addr, "TLS", acceptor.accept(socket)).await {
+                                    websocket::handle(stream, addr, id, state).await
+                                }
+                            }
+                            None => websocket::handle(socket, addr, id, state).await,
+                        }
+                    });
+                }
+                //reaping finished client tasks as we go
+                Some(_) = clients.join_next() => {}
+                _ = rate.tick() => state.metrics.sample(),
+                _ = &mut shutdown => break,
+            }
+        }
+
+        //telling every client task to say goodbye and close, then giving them a moment to do it
+        log::info!("Shutting down, waiting for {} clients", clients.len());
+        drop(listener);
+        drop(websocket);
+        let _ = shutdown_tx.send(true);
+        let drain = Duration::from_secs(state.config.drain_timeout_secs);
+        if tokio::time::timeout(drain, async { while clients.join_next().await.is_some() {} }).await.is_err() {
+            log::warn!("{} clients did not close in time", clients.len());
+        }
+        if let Some(scraping) = scraping {
+            scraping.abort();
+        }
+        log::info!("Server stopped");
+    }
+}
+
+//the next websocket connection, never resolving when there is no websocket listener
+async fn accept_websocket(listener: Option<&TcpListener>) -> io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+//function for handling client process
+//a tls or websocket handshake, which gets as long as a client has to log in. a peer that never
+//finishes it would otherwise hold its task and socket forever
+async fn within_handshake<T, E: std::fmt::Display>(
+    state: &State,
+    addr: SocketAddr,
+    what: &str,
+    handshake: impl Future<Output = Result<T, E>>,
+) -> Option<T> {
+    let handshake_timeout = Duration::from_secs(state.config.handshake_timeout_secs);
+    match tokio::time::timeout(handshake_timeout, handshake).await {
+        Ok(Ok(stream)) => Some(stream),
+        Ok(Err(e)) => {
+            log::warn!("{} handshake with {} failed: {}", what, addr, e);
+            None
+        }
+        Err(_) => {
+            log::info!("Dropping connection from {}, no {} handshake within {} seconds", addr, what, handshake_timeout.as_secs());
+            state.metrics.disconnected("handshake_timeout");
+            None
+        }
+    }
+}
+
+async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(socket: S, addr: SocketAddr, id: u64, state: Arc<State>)
+{
+    state.metrics.connection_opened();
+    let (reader, mut writer) = tokio::io::split(Counted::new(socket, state.metrics.clone()));
+    let mut reader = FrameReader::new(reader);
+
+    if state.banned.lock().await.contains(&addr.ip()) {
+        log::info!("Refusing banned address {}", addr);
+        let _ = write_message(&mut writer, &Message::Rejected { reason: "this address is banned".to_string() }).await;
+        state.metrics.disconnected("banned");
+        return;
+    }
+
+    //holding a slot for as long as the connection lives
+    let Ok(_slot) = state.slots.try_acquire() else {
+        log::warn!("Turning away connection {}, server is full", id);
+        let reason = format!("server is full ({} clients)", state.config.max_clients);
+        let _ = write_message(&mut writer, &Message::Rejected { reason }).await;
+        state.metrics.disconnected("server_full");
+        return;
+    };
+
+    //the first frame has to log in or register, nothing else is accepted before that.
+    //it has to come soon too, a connection that never sends one would hold its slot forever
+    let handshake_timeout = Duration::from_secs(state.config.handshake_timeout_secs);
+    let handshake = match tokio::time::timeout(handshake_timeout, reader.read_message()).await {
+        Ok(Ok(Some(message))) => message,
+        Ok(Ok(None)) => {
+            state.metrics.disconnected("client_closed");
+            return;
+        }
+        Ok(Err(e)) => {
+            log::warn!("Error reading frame: {}", e);
+            state.metrics.disconnected("read_error");
+            return;
+        }
+        Err(_) => {
+            log::info!("Dropping connection from {}, no login within {} seconds", addr, handshake_timeout.as_secs());
+            let reason = "no login in time".to_string();
+            let _ = write_message(&mut writer, &Message::Rejected { reason }).await;
+            state.metrics.disconnected("handshake_timeout");
+            return;
+        }
+    };
+
+    //checking the credentials, then claiming the name so a second connection can't log in as the same user
+    let authenticated = match authenticate(&state, addr.ip(), handshake).await {
+        Ok((name, resume)) => register_user(&state, &name, id, addr, resume.is_some())
+            .await
+            .map(|(inbox, files)| (name, resume, inbox, files)),
+        Err(reason) => Err(reason),
+    };
+    let (name, resume, mut inbox, mut files) = match authenticated {
+        Ok(authenticated) => authenticated,
+        Err(reason) => {
+            log::info!("Rejected connection from {}: {}", addr, reason);
+            let _ = write_message(&mut writer, &Message::Rejected { reason }).await;
+            state.metrics.disconnected("login_failed");
+            return;
+        }
+    };
+    log::info!("{} joined from {}", name, addr);
+
+    if write_message(&mut writer, &Message::Authenticated { name: name.clone() }).await.is_err() {
+        unregister_user(&state, &name, id).await;
+        state.metrics.disconnected("write_error");
+        return;
+    }
+    state.metrics.client_joined();
+
+    //a resuming client goes back to its room and only gets what it missed
+    let (mut room, seen) = match resume {
+        Some(Resume { room, seq }) if !room.trim().is_empty() => (room.trim().to_string(), Some(seq)),
+        _ => (DEFAULT_ROOM.to_string(), None),
+    };
+    let (mut rx, replay) = join_room(&state, &room, id, &name, seen).await;
+    //the oldest message of the room this client has been sent, /history pages back from it
+    let mut oldest = replay.first().map(|record| record.seq);
+
+    //catching the client up on the room before live traffic starts
+    for record in replay {
+        if let Err(e) = write_message(&mut writer, &sequenced(record)).await {
+            log::warn!("Error writing to socket: {}", e);
+            leave_room(&state, &room, id).await;
+            unregister_user(&state, &name, id).await;
+            state.metrics.client_left();
+            state.metrics.disconnected("write_error");
+            return;
+        }
+    }
+    broadcast(&state, &room, Message::Join { name: name.clone() }).await;
+    send_members(&state, &room).await;
+    presence::set(&state, &name, id, Status::Online).await;
+    for presence in presence::snapshot(&state, &name).await {
+        if write_message(&mut writer, &presence).await.is_err() {
+            break;
+        }
+    }
+
+    //going away after a while without activity, and back online with the next thing the user does.
+    //away set with /away sticks until the user comes back on purpose
+    let idle_after = Duration::from_secs(state.config.idle_secs);
+    let idle = tokio::time::sleep(idle_after);
+    tokio::pin!(idle);
+    let mut status = Status::Online;
+    let mut idle_away = false;
+
+    //consecutive times this client fell behind its room's broadcast channel
+    let mut lag_strikes = 0;
+
+    let mut shutdown = state.shutdown.clone();
+
+    let ended = loop {
+        //tokio select! macro will wait for task to complete concurrently and execute the task whichever completes earlier
+        tokio::select! {
+            frame = reader.read_message() => {
+                let message = match frame {
+                    Ok(Some(message)) => message,
+                    // connection is closed
+                    Ok(None) => break "client_closed",
+                    Err(e) => {
+                        log::warn!("Error reading frame: {}", e);
+                        break "read_error";
+                    }
+                };
+
+                state.metrics.frame_received();
+
+                //logging messages on server side, file data would only flood the log
+                if !matches!(message, Message::FileChunk { .. }) {
+                    log::debug!("Received message {:?} from {} in {}", message, name, room);
+                }
+
+                //delivery receipts are sent by the client on its own, they don't mean the user is there
+                if !matches!(message, Message::Receipt { read: false, .. }) {
+                    idle.as_mut().reset(Instant::now() + idle_after);
+                    if idle_away {
+                        idle_away = false;
+                        status = Status::Online;
+                        presence::set(&state, &name, id, status).await;
+                    }
+                }
+
+                let replies = match message {
+                    Message::Chat { text, .. } => {
+                        // for broadcasting the received message to everyone in the room
+                        state.metrics.chat_message();
+                        broadcast(&state, &room, Message::Chat { name: name.clone(), text }).await;
+                        Vec::new()
+                    }
+                    Message::Direct { to, text, .. } => {
+                        let direct = Message::Direct { from: name.clone(), to: to.clone(), text };
+                        if send_direct(&state, &to, direct.clone()).await {
+                            //echoing it back so the sender sees what went out
+                            vec![direct]
+                        } else {
+                            vec![system(format!("no user named {}", to))]
+                        }
+                    }
+                    Message::JoinRoom { room: target } => {
+                        let target = target.trim().to_string();
+                        if target.is_empty() {
+                            vec![system("usage: /join <room>".to_string())]
+                        } else if target == room {
+                            vec![system(format!("you are already in {}", room))]
+                        } else {
+                            let replay;
+                            (rx, replay) = switch_room(&state, &mut room, &target, id, &name).await;
+                            oldest = replay.first().map(|record| record.seq);
+                            let mut replies = vec![system(format!("you joined {}", room))];
+                            replies.extend(replay.into_iter().map(sequenced));
+                            replies
+                        }
+                    }
+                    Message::LeaveRoom => {
+                        if room == DEFAULT_ROOM {
+                            vec![system(format!("you are already in the {}", DEFAULT_ROOM))]
+                        } else {
+                            let replay;
+                            (rx, replay) = switch_room(&state, &mut room, DEFAULT_ROOM, id, &name).await;
+                            oldest = replay.first().map(|record| record.seq);
+                            let mut replies = vec![system(format!("you are back in the {}", DEFAULT_ROOM))];
+                            replies.extend(replay.into_iter().map(sequenced));
+                            replies
+                        }
+                    }
+                    offer @ Message::FileOffer { .. } => vec![files::offer(&state, &name, offer).await],
+                    answer @ (Message::FileAccept { .. } | Message::FileReject { .. }) => {
+                        files::answer(&state, &name, answer).await.into_iter().collect()
+                    }
+                    ack @ Message::FileAck { .. } => {
+                        files::acknowledge(&state, &name, ack).await;
+                        Vec::new()
+                    }
+                    piece @ (Message::FileChunk { .. } | Message::FileDone { .. }) => {
+                        match files::route(&state, &name, piece).await {
+                            Some((queue, piece)) => match queue.try_send(piece) {
+                                Ok(()) => Vec::new(),
+                                //the recipient isn't reading, waiting for it would hold up everything else this client sends
+                                Err(mpsc::error::TrySendError::Full(
+                                    Message::FileChunk { id, to, .. } | Message::FileDone { id, to, .. },
+                                )) => {
+                                    log::warn!("{} fell behind a file from {}, stopping it", to, name);
+                                    vec![files::stop(&state, &name, id, &to).await]
+                                }
+                                //the recipient just left, nobody is waiting for the rest
+                                Err(_) => Vec::new(),
+                            },
+                            None => Vec::new(),
+                        }
+                    }
+                    Message::SetStatus { status: Status::Offline } => {
+                        vec![system("you can only set yourself online or away".to_string())]
+                    }
+                    Message::SetStatus { status: wanted } => {
+                        status = wanted;
+                        idle_away = false;
+                        presence::set(&state, &name, id, status).await;
+                        vec![system(format!("you are {}", if status == Status::Away { "away" } else { "online" }))]
+                    }
+                    Message::Typing { .. } => {
+                        presence::typing(&state, &name, &room).await;
+                        Vec::new()
+                    }
+                    Message::Receipt { seq, author, read, .. } => {
+                        presence::receipt(&state, &name, seq, &author, read).await;
+                        Vec::new()
+                    }
+                    Message::ListRooms => vec![system(list_rooms(&state).await)],
+                    Message::Who => vec![system(list_members(&state, &room).await)],
+                    Message::History { count } => {
+                        let count = count.min(MAX_HISTORY_REQUEST);
+                        let records = {
+                            let history = state.history.lock().await;
+                            match oldest {
+                                Some(seq) => history.before(&room, seq, count),
+                                None => history.recent(&room, count),
+                            }
+                        };
+                        if let Some(record) = records.first() {
+                            oldest = Some(record.seq);
+                        }
+                        let mut replies = records.into_iter().map(|record| record.message).collect::<Vec<_>>();
+                        replies.push(system(format!("end of history for {}", room)));
+                        replies
+                    }
+                    other => vec![system(format!("unexpected message {:?}", other))],
+                };
+
+                //answers to commands only go back to the client who asked
+                let mut failed = false;
+                for reply in &replies {
+                    if let Err(e) = write_message(&mut writer, reply).await {
+                        log::warn!("Error writing to socket: {}", e);
+                        failed = true;
+                        break;
+                    }
+                }
+                if failed {
+                    break "write_error";
+                }
+            }
+            received = rx.recv() => {
+                // receiving messages from the room's broadcast channel ,if a message is received, it is written back to the client
+                let message = match received {
+                    Ok(record) => {
+                        //an empty receiver means the client has caught up with the room
+                        if rx.is_empty() {
+                            lag_strikes = 0;
+                        }
+                        oldest.get_or_insert(record.seq);
+                        sequenced(record)
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        lag_strikes += 1;
+                        //paging back from the next message that does arrive, so /history starts with the ones missed
+                        oldest = None;
+                        state.metrics.lagged(missed);
+                        log::warn!("{} lagged behind {} by {} messages ({} in a row)", name, room, missed, lag_strikes);
+                        if lag_strikes >= state.config.max_lag_strikes {
+                            let notice = system(format!("disconnected: you missed {} messages and kept falling behind", missed));
+                            let _ = write_message(&mut writer, &notice).await;
+                            break "lagging";
+                        }
+                        system(format!("you missed {} messages, use /history to catch up", missed))
+                    }
+                    //the room holds its sender while we are a member, so this only happens if the room is gone
+                    Err(broadcast::error::RecvError::Closed) => break "room_closed",
+                };
+
+                if let Err(e) = write_message(&mut writer, &message).await {
+                    log::warn!("Error writing to socket: {}", e);
+                    break "write_error";
+                }
+            }
+            //file chunks for this client, picked like any other branch so they interleave with chat instead of holding it up
+            Some(piece) = files.recv() => {
+                if let Err(e) = write_message(&mut writer, &piece).await {
+                    log::warn!("Error writing to socket: {}", e);
+                    break "write_error";
+                }
+            }
+            () = &mut idle, if status == Status::Online => {
+                status = Status::Away;
+                idle_away = true;
+                presence::set(&state, &name, id, status).await;
+            }
+            Some(control) = inbox.recv() => {
+                match control {
+                    // private messages addressed to this user
+                    Control::Deliver(message) => {
+                        if let Err(e) = write_message(&mut writer, &message).await {
+                            log::warn!("Error writing to socket: {}", e);
+                            break "write_error";
+                        }
+                    }
+                    Control::Kick(reason) => {
+                        log::info!("Kicking {}: {}", name, reason);
+                        let _ = write_message(&mut writer, &Message::Rejected { reason }).await;
+                        break "kicked";
+                    }
+                }
+            }
+            _ = shutdown.changed() => {
+                //flushing whatever was already queued for this client before saying goodbye
+                let mut pending = Vec::new();
+                while let Ok(record) = rx.try_recv() {
+                    pending.push(sequenced(record));
+                }
+                while let Ok(Control::Deliver(message)) = inbox.try_recv() {
+                    pending.push(message);
+                }
+                pending.push(system("the server is shutting down".to_string()));
+
+                for message in &pending {
+                    if write_message(&mut writer, message).await.is_err() {
+                        break;
+                    }
+                }
+                let _ = writer.shutdown().await;
+                break "shutdown";
+            }
+        }
+    };
+    state.metrics.client_left();
+    state.metrics.disconnected(ended);
+
+    //letting everyone else in the room know this client is gone. if a reconnect took the name over,
+    //the user is still here and its file offers carry on with the new connection
+    leave_room(&state, &room, id).await;
+    if unregister_user(&state, &name, id).await {
+        broadcast(&state, &room, Message::Leave { name: name.clone() }).await;
+        presence::set(&state, &name, id, Status::Offline).await;
+        files::forget(&state, &name).await;
+    }
+    send_members(&state, &room).await;
+}
+
+//shorthand for a server notice
+fn system(text: String) -> Message {
+    Message::System { text }
+}
+
+//a history record as sent to clients, carrying the seq they resume from
+fn sequenced(record: Record) -> Message {
+    Message::Sequenced { seq: record.seq, message: Box::new(record.message) }
+}
+
+//handling the login handshake, returning the authenticated user name and where a reconnecting client left off
+async fn authenticate(state: &State, ip: IpAddr, handshake: Message) -> Result<(String, Option<Resume>), String> {
+    if state.login_limiter.lock().await.is_blocked(ip) {
+        return Err("too many failed logins, try again later".to_string());
+    }
+
+    match handshake {
+        Message::Register { name, password } => {
+            //registering counts against the same budget, or it would be a free way to find out which names exist
+            if !state.login_limiter.lock().await.attempt(ip) {
+                return Err("too many failed logins, try again later".to_string());
+            }
+
+            let name = name.trim().to_string();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err("names must be non-empty and contain no spaces".to_string());
+            }
+            if password.is_empty() {
+                return Err("the password must not be empty".to_string());
+            }
+
+            //hashing is deliberately slow, so it runs on the blocking pool. it happens before the name is looked up
+            //so a taken name takes as long to refuse as a free one
+            let iterations = state.config.hash_iterations;
+            let account = tokio::task::spawn_blocking(move || Account::new(&password, iterations))
+                .await
+                .map_err(|_| "could not create the account".to_string())?;
+            {
+                let mut accounts = state.accounts.lock().await;
+                //a taken name is refused like a failed login, it doesn't say the account exists
+                if accounts.get(&name).is_some() {
+                    return Err("invalid name or password".to_string());
+                }
+                accounts.insert(&name, account)?;
+            }
+            state.login_limiter.lock().await.refund(ip);
+            Ok((name, None))
+        }
+        Message::Login { name, password, resume } => {
+            if !state.login_limiter.lock().await.attempt(ip) {
+                return Err("too many failed logins, try again later".to_string());
+            }
+
+            //unknown names are hashed too, so they take as long to refuse as a wrong password
+            let account = state.accounts.lock().await.get(&name);
+            let iterations = state.config.hash_iterations;
+            let verified = tokio::task::spawn_blocking(move || match account {
+                Some(account) => account.verify(&password),
+                None => verify_unknown(&password, iterations),
+            })
+            .await
+            .unwrap_or(false);
+
+            if verified {
+                state.login_limiter.lock().await.record_success(ip);
+                Ok((name, resume))
+            } else {
+                Err("invalid name or password".to_string())
+            }
+        }
+        _ => Err("log in or register first".to_string()),
+    }
+}
+
+//claiming a username for this connection, returning the inbox other tasks reach it through and its file queue.
+//a reconnecting client takes over from a connection of its own the server hasn't noticed is dead yet
+async fn register_user(
+    state: &State,
+    name: &str,
+    id: u64,
+    addr: SocketAddr,
+    takeover: bool,
+) -> Result<(mpsc::UnboundedReceiver<Control>, mpsc::Receiver<Message>), String> {
+    let mut users = state.users.lock().await;
+    if let Some(existing) = users.get(name) {
+        if !takeover {
+            return Err(format!("the name {} is already taken", name));
+        }
+        let _ = existing.tx.send(Control::Kick("you logged in from another connection".to_string()));
+    }
+    let (tx, rx) = mpsc::unbounded_channel();
+    let (files, files_rx) = mpsc::channel(files::QUEUE_LEN);
+    //announced as online once the client is in its room
+    users.insert(name.to_string(), UserHandle { id, addr, tx, files, status: Status::Offline });
+    Ok((rx, files_rx))
+}
+
+//giving up a username, unless another connection has taken it over in the meantime. true if it was ours
+async fn unregister_user(state: &State, name: &str, id: u64) -> bool {
+    let mut users = state.users.lock().await;
+    let owned = users.get(name).is_some_and(|handle| handle.id == id);
+    if owned {
+        users.remove(name);
+    }
+    owned
+}
+
+//delivering a private message to one user, false if nobody has that name
+async fn send_direct(state: &State, to: &str, message: Message) -> bool {
+    match state.users.lock().await.get(to) {
+        Some(handle) => handle.tx.send(Control::Deliver(message)).is_ok(),
+        None => false,
+    }
+}
+
+//telling everyone in a room who is in it, so clients can keep a member list.
+//goes through the users' inboxes instead of the room channel so it stays out of the history
+async fn send_members(state: &State, room: &str) {
+    let users = state.users.lock().await;
+    let rooms = state.rooms.lock().await;
+    let Some(entry) = rooms.get(room) else {
+        return;
+    };
+
+    let mut names = entry.members.values().cloned().collect::<Vec<_>>();
+    names.sort();
+    for name in &names {
+        if let Some(handle) = users.get(name) {
+            let _ = handle.tx.send(Control::Deliver(Message::Members { room: room.to_string(), names: names.clone() }));
+        }
+    }
+}
+
+//adding a client to a room, creating the room if it doesn't exist yet.
+//returns the room's receiver together with the messages to replay: everything after `seen` if given, otherwise the most recent ones
+async fn join_room(state: &State, room: &str, id: u64, name: &str, seen: Option<u64>) -> (broadcast::Receiver<Record>, Vec<Record>) {
+    //holding the history lock while subscribing means every message is either in the replay or on the receiver, never both
+    let history = state.history.lock().await;
+    let mut rooms = state.rooms.lock().await;
+    let entry = rooms.entry(room.to_string()).or_insert_with(|| {
+        let (tx, _rx) = broadcast::channel(state.config.room_capacity);
+        Room { tx, members: HashMap::new() }
+    });
+    entry.members.insert(id, name.to_string());
+    let replay = match seen {
+        //a client that was away for long only gets as much as it could have asked for with /history
+        Some(seq) => history.since(room, seq, MAX_HISTORY_REQUEST),
+        None => history.recent(room, state.config.history_replay),
+    };
+    (entry.tx.subscribe(), replay)
+}
+
+//removing a client from a room, the room goes away once it is empty
+async fn leave_room(state: &State, room: &str, id: u64) {
+    let mut rooms = state.rooms.lock().await;
+    let Some(entry) = rooms.get_mut(room) else {
+        return;
+    };
+    entry.members.remove(&id);
+    if entry.members.is_empty() {
+        rooms.remove(room);
+    }
+}
+
+//moving a client from its current room to another one, notifying both rooms
+async fn switch_room(state: &State, room: &mut String, target: &str, id: u64, name: &str) -> (broadcast::Receiver<Record>, Vec<Record>) {
+    leave_room(state, room, id).await;
+    broadcast(state, room, system(format!("{} left {}", name, room))).await;
+
+    let (rx, replay) = join_room(state, target, id, name, None).await;
+    broadcast(state, target, system(format!("{} joined {}", name, target))).await;
+    send_members(state, room).await;
+    send_members(state, target).await;
+    *room = target.to_string();
+    (rx, replay)
+}
+
+//logging a message to the history and sending it to everyone in the room
+async fn broadcast(state: &State, room: &str, message: Message) {
+    //nobody left in the room means there is nobody to tell
+    let Some(tx) = state.rooms.lock().await.get(room).map(|room| room.tx.clone()) else {
+        return;
+    };
+
+    //sending while still holding the history lock keeps log order and delivery order the same
+    let mut history = state.history.lock().await;
+    let record = history.append(room, message);
+    //an error only means every receiver is gone, which is not worth failing the sender's task over
+    if tx.send(record).is_err() {
+        log::debug!("Nobody left in {} to receive the message", room);
+    }
+}
+
+//formatting the /rooms answer
+async fn list_rooms(state: &State) -> String {
+    let rooms = state.rooms.lock().await;
+    let mut names = rooms
+        .iter()
+        .map(|(name, room)| format!("{} ({})", name, room.members.len()))
+        .collect::<Vec<_>>();
+    names.sort();
+    format!("rooms: {}", names.join(", "))
+}
+
+//formatting the /who answer
+async fn list_members(state: &State, room: &str) -> String {
+    let rooms = state.rooms.lock().await;
+    let mut members = rooms
+        .get(room)
+        .map(|room| room.members.values().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    members.sort();
+    format!("in {}: {}", room, members.join(", "))
+}
@@ -1,57 +1,59 @@
-use tokio::net::{TcpListener, TcpStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::broadcast;
+use server::{Config, Server};
+use tokio::net::TcpListener;
 
 #[tokio::main]
 async fn main() {
 
-    //binding to address
-    let listener = TcpListener::bind("127.0.0.1:8080").await.unwrap();
-    println!("Server listening on 127.0.0.1:8080");
+    let config = Config::load();
+    env_logger::Builder::new().parse_filters(&config.log_level).init();
 
-    //creating a broadcast channel to communicate messages to multiple clients
-    let (tx, _rx) = broadcast::channel(100);
+    //binding to address
+    let bind = config.bind.clone();
+    let ws_bind = config.ws_bind.clone();
+    let metrics_bind = config.metrics_bind.clone();
+    let mut server = Server::new(config).expect("Failed to start server");
+    let listener = TcpListener::bind(&bind).await.expect("Failed to bind listener");
+    log::info!("Server listening on {}{}", bind, if server.is_tls() { " (tls)" } else { "" });
 
-    //accepting connection from different clients
-    while let Ok((socket, _)) = listener.accept().await {
-        let tx = tx.clone();
-        let rx = tx.subscribe();
+    //browsers connect through websockets on a port of their own
+    if let Some(ws_bind) = ws_bind {
+        let listener = TcpListener::bind(&ws_bind).await.expect("Failed to bind websocket listener");
+        log::info!("WebSocket gateway listening on {}", ws_bind);
+        server.listen_websocket(listener);
+    }
 
-        // sspawning task for each connected client
-        tokio::spawn(async move {
-            handle_client(socket, tx, rx).await;
-        });
+    if let Some(metrics_bind) = metrics_bind {
+        let listener = TcpListener::bind(&metrics_bind).await.expect("Failed to bind metrics listener");
+        log::info!("Metrics on http://{}/metrics", metrics_bind);
+        server.listen_metrics(listener);
     }
+
+    //admin commands typed into the server's terminal
+    server.spawn_admin_console();
+
+    //serving clients until ctrl-c or sigterm
+    server.run(listener, shutdown_signal()).await;
+
+    //the admin console blocks on stdin, exiting here keeps the runtime from waiting on it
+    std::process::exit(0);
 }
 
-//function for handling client process
-async fn handle_client(mut socket: TcpStream, tx: broadcast::Sender<String>, mut rx: broadcast::Receiver<String>) 
-{   
-    let (mut reader, mut writer) = socket.split();
-    let mut buffer = [0; 1024];
-    
-    loop {
-        //tokio select! macro will wait for task to complete concurrently and execute the task whichever completes earlier
+//resolving once the process is asked to stop with ctrl-c or sigterm
+async fn shutdown_signal() {
+    let ctrl_c = tokio::signal::ctrl_c();
+
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM");
         tokio::select! {
-            Ok(bytes_read) = reader.read(&mut buffer) => {
-                if bytes_read == 0 {
-                    // connection is closed
-                    break;
-                } else {
-                    // for broadcasting the received message to all clients
-                    let message = String::from_utf8_lossy(&buffer[..bytes_read]).to_string();
-                    //logging messages on server side
-                    println!("Received message {}",message.clone() );
-                    tx.send(message).unwrap();
-                }
-            }
-            Ok(message) = rx.recv() => {
-                // receiving messages from the broadcast channel ,if a message is received, it is written back to the client 
-                if let Err(e) = writer.write_all(message.as_bytes()).await {
-                    eprintln!("Error writing to socket: {}", e);
-                    break;
-                }
-            }
+            _ = ctrl_c => {}
+            _ = terminate.recv() => {}
         }
     }
-}
\ No newline at end of file
+
+    #[cfg(not(unix))]
+    {
+        let _ = ctrl_c.await;
+    }
+}
@@ -0,0 +1,201 @@
+use crate::State;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+
+//the longest request head we read before giving up on a scraper
+const MAX_REQUEST: usize = 8 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+//counters kept while the server runs, shared by every connection
+#[derive(Default)]
+pub struct Metrics {
+    connections: AtomicU64,
+    connected: AtomicU64,
+    frames_in: AtomicU64,
+    chat_messages: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    lagged: AtomicU64,
+    missed: AtomicU64,
+    //chat messages in the last whole second, updated by `sample`
+    per_second: AtomicU64,
+    last_sample: AtomicU64,
+    disconnects: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Metrics {
+    pub fn connection_opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    //a user finished logging in
+    pub fn client_joined(&self) {
+        self.connected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_left(&self) {
+        self.connected.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn frame_received(&self) {
+        self.frames_in.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn chat_message(&self) {
+        self.chat_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    //a receiver fell behind its room and skipped `missed` messages
+    pub fn lagged(&self, missed: u64) {
+        self.lagged.fetch_add(1, Ordering::Relaxed);
+        self.missed.fetch_add(missed, Ordering::Relaxed);
+    }
+
+    pub fn disconnected(&self, reason: &'static str) {
+        *self.disconnects.lock().unwrap().entry(reason).or_default() += 1;
+    }
+
+    //turning the chat message counter into a rate, called once a second
+    pub fn sample(&self) {
+        let total = self.chat_messages.load(Ordering::Relaxed);
+        let last = self.last_sample.swap(total, Ordering::Relaxed);
+        self.per_second.store(total - last, Ordering::Relaxed);
+    }
+
+    //everything in the prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: &AtomicU64| {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value.load(Ordering::Relaxed));
+        };
+        metric("chat_connections_total", "counter", "Connections accepted, logged in or not.", &self.connections);
+        metric("chat_connected_clients", "gauge", "Users currently logged in.", &self.connected);
+        metric("chat_frames_received_total", "counter", "Frames received from logged in clients.", &self.frames_in);
+        metric("chat_messages_total", "counter", "Chat messages sent to rooms.", &self.chat_messages);
+        metric("chat_messages_per_second", "gauge", "Chat messages sent to rooms in the last second.", &self.per_second);
+        metric("chat_bytes_received_total", "counter", "Bytes read from client connections.", &self.bytes_in);
+        metric("chat_bytes_sent_total", "counter", "Bytes written to client connections.", &self.bytes_out);
+        metric("chat_lagged_receivers_total", "counter", "Times a client fell behind its room.", &self.lagged);
+        metric("chat_missed_messages_total", "counter", "Room messages skipped by lagging clients.", &self.missed);
+
+        out.push_str("# HELP chat_disconnects_total Connections closed, by reason.\n# TYPE chat_disconnects_total counter\n");
+        for (reason, count) in self.disconnects.lock().unwrap().iter() {
+            let _ = writeln!(out, "chat_disconnects_total{{reason=\"{}\"}} {}", reason, count);
+        }
+        out
+    }
+}
+
+// a client connection that counts the bytes going through it
+pub struct Counted<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S> Counted<S> {
+    pub fn new(inner: S, metrics: Arc<Metrics>) -> Counted<S> {
+        Counted { inner, metrics }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let polled = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        self.metrics.bytes_in.fetch_add(read as u64, Ordering::Relaxed);
+        polled
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let polled = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = polled {
+            self.metrics.bytes_out.fetch_add(written as u64, Ordering::Relaxed);
+        }
+        polled
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// answering scrapers on `listener`: /metrics in the prometheus text format and /healthz,
+// which turns unhealthy once the server starts shutting down
+pub async fn serve(listener: TcpListener, state: Arc<State>) {
+    loop {
+        let (socket, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log::warn!("Failed to accept metrics connection: {}", e);
+                continue;
+            }
+        };
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond(socket, &state).await {
+                log::debug!("Metrics request from {} failed: {}", addr, e);
+            }
+        });
+    }
+}
+
+//one request per connection, http/1.0 style
+async fn respond(mut socket: TcpStream, state: &State) -> io::Result<()> {
+    let head = tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut socket))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    let mut parts = head.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    //query strings don't change anything here
+    let path = path.split('?').next().unwrap_or("");
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", "text/plain; version=0.0.4", state.metrics.render()),
+        ("GET", "/healthz") if *state.shutdown.borrow() => ("503 Service Unavailable", "text/plain", "shutting down\n".to_string()),
+        ("GET", "/healthz") => ("200 OK", "text/plain", "ok\n".to_string()),
+        ("GET", _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "method not allowed\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+//reading up to the blank line that ends the request head
+async fn read_head(socket: &mut TcpStream) -> io::Result<String> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request too large"));
+        }
+        let read = socket.read(&mut buffer).await?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
@@ -0,0 +1,61 @@
+use crate::{send_direct, Control, State};
+use lib::{Message, Status};
+
+//recording a user's status and telling everyone connected about it, nothing is sent if it didn't change.
+//offline is only announced by the connection that held the name, a takeover keeps the user online
+pub async fn set(state: &State, name: &str, id: u64, status: Status) {
+    let mut users = state.users.lock().await;
+    match users.get_mut(name) {
+        Some(handle) if handle.id == id => {
+            if handle.status == status {
+                return;
+            }
+            handle.status = status;
+        }
+        //the user is already gone from the map when it disconnects
+        None if status == Status::Offline => {}
+        _ => return,
+    }
+
+    let presence = Message::Presence { name: name.to_string(), status };
+    for (other, handle) in users.iter() {
+        if other != name {
+            let _ = handle.tx.send(Control::Deliver(presence.clone()));
+        }
+    }
+}
+
+//who is away right now, for a client that just logged in. everyone else connected is online
+pub async fn snapshot(state: &State, name: &str) -> Vec<Message> {
+    let users = state.users.lock().await;
+    let mut away = users
+        .iter()
+        .filter(|(other, handle)| *other != name && handle.status == Status::Away)
+        .map(|(other, _)| other.clone())
+        .collect::<Vec<_>>();
+    away.sort();
+    away.into_iter().map(|name| Message::Presence { name, status: Status::Away }).collect()
+}
+
+//passing a typing notification on to everyone else in the room
+pub async fn typing(state: &State, name: &str, room: &str) {
+    let users = state.users.lock().await;
+    let rooms = state.rooms.lock().await;
+    let Some(entry) = rooms.get(room) else {
+        return;
+    };
+
+    let typing = Message::Typing { name: name.to_string(), room: room.to_string() };
+    for member in entry.members.values().filter(|member| *member != name) {
+        if let Some(handle) = users.get(member) {
+            let _ = handle.tx.send(Control::Deliver(typing.clone()));
+        }
+    }
+}
+
+//routing a delivery or read receipt to the author of the message, acknowledging your own messages goes nowhere
+pub async fn receipt(state: &State, by: &str, seq: u64, author: &str, read: bool) {
+    if author != by {
+        send_direct(state, author, Message::Receipt { seq, author: author.to_string(), by: by.to_string(), read }).await;
+    }
+}
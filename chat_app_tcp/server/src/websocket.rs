@@ -0,0 +1,102 @@
+use crate::{handle_client, within_handshake, State};
+use futures_util::{SinkExt, StreamExt};
+use lib::{write_message, FrameReader, Message, MAX_FRAME_LEN};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, WebSocketConfig};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::WebSocketStream;
+
+//room for a couple of frames between the websocket and the chat connection
+const PIPE_SIZE: usize = 64 * 1024;
+
+// serving a browser: every websocket text message is one chat message in the same json as the tcp frames.
+// the chat side is the regular client handler, fed through an in-memory pipe, so browsers share rooms,
+// history and everything else with the terminal clients
+pub async fn handle<S: AsyncRead + AsyncWrite + Unpin>(socket: S, addr: SocketAddr, id: u64, state: Arc<State>) {
+    let config = WebSocketConfig { max_message_size: Some(MAX_FRAME_LEN), ..WebSocketConfig::default() };
+    let upgrade = tokio_tungstenite::accept_async_with_config(socket, Some(config));
+    let Some(websocket) = within_handshake(&state, addr, "WebSocket", upgrade).await else {
+        return;
+    };
+
+    let (chat, pipe) = tokio::io::duplex(PIPE_SIZE);
+    tokio::join!(handle_client(chat, addr, id, state), bridge(websocket, pipe, addr));
+}
+
+//passing messages between the browser and the chat connection until the chat side closes
+async fn bridge<S: AsyncRead + AsyncWrite + Unpin>(websocket: WebSocketStream<S>, pipe: DuplexStream, addr: SocketAddr) {
+    let (mut sink, mut stream) = websocket.split();
+    let (reader, mut writer) = tokio::io::split(pipe);
+    let mut reader = FrameReader::new(reader);
+    //once the browser is gone the chat side still has to be read until it finishes cleaning up
+    let mut open = true;
+
+    loop {
+        tokio::select! {
+            incoming = stream.next(), if open => {
+                let text = match incoming {
+                    Some(Ok(WsMessage::Text(text))) => text,
+                    Some(Ok(WsMessage::Binary(data))) => String::from_utf8_lossy(&data).into_owned(),
+                    //pings are answered by tungstenite itself
+                    Some(Ok(WsMessage::Ping(_) | WsMessage::Pong(_) | WsMessage::Frame(_))) => continue,
+                    Some(Ok(WsMessage::Close(_))) | None => {
+                        open = false;
+                        let _ = writer.shutdown().await;
+                        continue;
+                    }
+                    Some(Err(e)) => {
+                        log::warn!("Error reading from websocket {}: {}", addr, e);
+                        open = false;
+                        let _ = writer.shutdown().await;
+                        continue;
+                    }
+                };
+
+                match serde_json::from_str::<Message>(&text) {
+                    Ok(message) => {
+                        if write_message(&mut writer, &message).await.is_err() {
+                            break;
+                        }
+                    }
+                    //the browser is told what was wrong, the chat side just sees the connection end
+                    Err(e) => {
+                        let reason = format!("invalid message: {}", e);
+                        let close = CloseFrame { code: CloseCode::Invalid, reason: reason.chars().take(100).collect::<String>().into() };
+                        let _ = sink.send(WsMessage::Close(Some(close))).await;
+                        open = false;
+                        let _ = writer.shutdown().await;
+                    }
+                }
+            }
+            outgoing = reader.read_message() => {
+                let message = match outgoing {
+                    Ok(Some(message)) => message,
+                    //the chat side is done with this client
+                    _ => break,
+                };
+                if !open {
+                    continue;
+                }
+                let text = match serde_json::to_string(&message) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        log::warn!("Failed to encode message for {}: {}", addr, e);
+                        continue;
+                    }
+                };
+                if let Err(e) = sink.send(WsMessage::Text(text)).await {
+                    log::warn!("Error writing to websocket {}: {}", addr, e);
+                    open = false;
+                    let _ = writer.shutdown().await;
+                }
+            }
+        }
+    }
+
+    if open {
+        let _ = sink.close().await;
+    }
+}
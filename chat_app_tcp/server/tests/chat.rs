@@ -0,0 +1,770 @@
+use futures_util::{SinkExt, StreamExt};
+use lib::{write_message, FrameReader, Message, Resume, Status};
+use server::{Config, Server};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+//how long a test waits for a frame before giving up
+const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+//a server running in-process on an ephemeral port
+struct TestServer {
+    addr: SocketAddr,
+    //where browsers would connect
+    ws_addr: SocketAddr,
+    //the http endpoint with /metrics and /healthz
+    metrics_addr: SocketAddr,
+    //the admin console, commands go in and their answers come back line by line
+    console: BufReader<DuplexStream>,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: JoinHandle<()>,
+}
+
+impl TestServer {
+    async fn start() -> TestServer {
+        TestServer::start_with(&[]).await
+    }
+
+    //starting with extra command line flags
+    async fn start_with(flags: &[&str]) -> TestServer {
+        let dir = scratch_dir();
+        let mut args: Vec<PathBuf> = vec![
+            "server".into(),
+            "--history-file".into(),
+            dir.join("history.log"),
+            "--accounts-file".into(),
+            dir.join("accounts.txt"),
+            "--drain-timeout-secs".into(),
+            "2".into(),
+        ];
+        //fast hashing unless a test asks for something else
+        if !flags.contains(&"--hash-iterations") {
+            args.extend(["--hash-iterations".into(), "1".into()]);
+        }
+        args.extend(flags.iter().map(PathBuf::from));
+        let config = Config::from_args(args);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ws_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ws_addr = ws_listener.local_addr().unwrap();
+        let (shutdown, stop) = oneshot::channel::<()>();
+        let mut server = Server::new(config).unwrap();
+        server.listen_websocket(ws_listener);
+        let metrics_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let metrics_addr = metrics_listener.local_addr().unwrap();
+        server.listen_metrics(metrics_listener);
+        let (console, admin) = tokio::io::duplex(4096);
+        let (input, output) = tokio::io::split(admin);
+        server.spawn_admin(input, output);
+        let task = tokio::spawn(server.run(listener, async {
+            let _ = stop.await;
+        }));
+        TestServer { addr, ws_addr, metrics_addr, console: BufReader::new(console), shutdown: Some(shutdown), task }
+    }
+
+    //registering a fresh account and waiting until it is in the lobby
+    async fn join(&self, name: &str) -> TestClient {
+        let mut client = TestClient::connect(self.addr).await;
+        client.send(&Message::Register { name: name.to_string(), password: "secret".to_string() }).await;
+        assert_eq!(client.recv().await, Message::Authenticated { name: name.to_string() });
+        client.recv_until(|message| *message == Message::Join { name: name.to_string() }).await;
+        client
+    }
+
+    //a plain http get, returning the status line and the body
+    async fn get(&self, path: &str) -> (String, String) {
+        let mut socket = TcpStream::connect(self.metrics_addr).await.unwrap();
+        socket.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes()).await.unwrap();
+        let mut response = String::new();
+        tokio::time::timeout(RECV_TIMEOUT, socket.read_to_string(&mut response)).await.unwrap().unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    //typing a command into the admin console, returning the first line of its answer
+    async fn admin(&mut self, command: &str) -> String {
+        self.console.get_mut().write_all(format!("{}\n", command).as_bytes()).await.unwrap();
+        let mut answer = String::new();
+        tokio::time::timeout(RECV_TIMEOUT, self.console.read_line(&mut answer)).await.unwrap().unwrap();
+        answer.trim_end().to_string()
+    }
+
+    async fn stop(mut self) {
+        self.shutdown.take().unwrap().send(()).unwrap();
+        tokio::time::timeout(RECV_TIMEOUT, self.task).await.unwrap().unwrap();
+    }
+}
+
+//a scripted client speaking the frame protocol directly
+struct TestClient {
+    reader: FrameReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    //seq of the last room message received, what a real client would resume from
+    seq: u64,
+}
+
+impl TestClient {
+    async fn connect(addr: SocketAddr) -> TestClient {
+        let (reader, writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        TestClient { reader: FrameReader::new(reader), writer, seq: 0 }
+    }
+
+    async fn send(&mut self, message: &Message) {
+        write_message(&mut self.writer, message).await.unwrap();
+    }
+
+    async fn chat(&mut self, text: &str) {
+        self.send(&Message::Chat { name: String::new(), text: text.to_string() }).await;
+    }
+
+    //the next frame, or None once the server closed the connection
+    async fn try_recv(&mut self) -> Option<Message> {
+        tokio::time::timeout(RECV_TIMEOUT, self.reader.read_message())
+            .await
+            .expect("timed out waiting for a frame")
+            .unwrap()
+    }
+
+    //the next frame with room messages unwrapped from their seq
+    async fn recv(&mut self) -> Message {
+        match self.try_recv().await.expect("connection closed") {
+            Message::Sequenced { seq, message } => {
+                self.seq = seq;
+                *message
+            }
+            message => message,
+        }
+    }
+
+    //skipping frames until one matches
+    async fn recv_until(&mut self, wanted: impl Fn(&Message) -> bool) -> Message {
+        loop {
+            let message = self.recv().await;
+            if wanted(&message) {
+                return message;
+            }
+        }
+    }
+
+    //the next chat message, skipping notices
+    async fn recv_chat(&mut self) -> (String, String) {
+        match self.recv_until(|message| matches!(message, Message::Chat { .. })).await {
+            Message::Chat { name, text } => (name, text),
+            _ => unreachable!(),
+        }
+    }
+}
+
+//the next chat message from a websocket, every text message carries one in the frame json
+async fn ws_recv_until(browser: &mut WebSocket, wanted: impl Fn(&Message) -> bool) -> Message {
+    loop {
+        let frame = tokio::time::timeout(RECV_TIMEOUT, browser.next()).await.unwrap().unwrap().unwrap();
+        let WsMessage::Text(text) = frame else {
+            continue;
+        };
+        let message = match serde_json::from_str(&text).unwrap() {
+            Message::Sequenced { message, .. } => *message,
+            message => message,
+        };
+        if wanted(&message) {
+            return message;
+        }
+    }
+}
+
+fn scratch_dir() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "chat_server_test_{}_{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn broadcasts_arrive_in_order() {
+    let server = TestServer::start().await;
+    let mut alice = server.join("alice").await;
+    let mut bob = server.join("bob").await;
+
+    for i in 0..50 {
+        alice.chat(&format!("message {}", i)).await;
+    }
+    for i in 0..50 {
+        assert_eq!(bob.recv_chat().await, ("alice".to_string(), format!("message {}", i)));
+        assert_eq!(alice.recv_chat().await, ("alice".to_string(), format!("message {}", i)));
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn disconnect_is_announced_to_the_room() {
+    let server = TestServer::start().await;
+    let mut alice = server.join("alice").await;
+    let bob = server.join("bob").await;
+
+    drop(bob);
+    alice.recv_until(|message| *message == Message::Leave { name: "bob".to_string() }).await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn rooms_only_see_their_own_messages() {
+    let server = TestServer::start().await;
+    let mut alice = server.join("alice").await;
+    let mut bob = server.join("bob").await;
+    let mut carl = server.join("carl").await;
+
+    alice.send(&Message::JoinRoom { room: "dev".to_string() }).await;
+    alice.recv_until(|message| *message == Message::System { text: "you joined dev".to_string() }).await;
+    carl.send(&Message::JoinRoom { room: "dev".to_string() }).await;
+    carl.recv_until(|message| *message == Message::System { text: "you joined dev".to_string() }).await;
+
+    bob.chat("lobby only").await;
+    assert_eq!(bob.recv_chat().await.1, "lobby only");
+    carl.chat("dev only").await;
+    assert_eq!(alice.recv_chat().await.1, "dev only");
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn direct_messages_reach_only_the_recipient() {
+    let server = TestServer::start().await;
+    let mut alice = server.join("alice").await;
+    let mut bob = server.join("bob").await;
+
+    alice.send(&Message::Direct { from: String::new(), to: "bob".to_string(), text: "psst".to_string() }).await;
+    let expected = Message::Direct { from: "alice".to_string(), to: "bob".to_string(), text: "psst".to_string() };
+    assert_eq!(bob.recv_until(|message| matches!(message, Message::Direct { .. })).await, expected);
+    assert_eq!(alice.recv_until(|message| matches!(message, Message::Direct { .. })).await, expected);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn history_pages_back_through_older_messages() {
+    let server = TestServer::start_with(&["--history-replay", "2"]).await;
+    let mut alice = server.join("alice").await;
+    for text in ["one", "two", "three", "four", "five"] {
+        alice.chat(text).await;
+        alice.recv_chat().await;
+    }
+
+    //bob is caught up on the last two, every /history goes further back from there
+    let mut bob = TestClient::connect(server.addr).await;
+    bob.send(&Message::Register { name: "bob".to_string(), password: "secret".to_string() }).await;
+    let mut page = Vec::new();
+    loop {
+        match bob.recv().await {
+            Message::Chat { text, .. } => page.push(text),
+            Message::Join { name } if name == "bob" => break,
+            _ => {}
+        }
+    }
+    assert_eq!(page, ["four", "five"]);
+
+    for expected in [&["two", "three"][..], &["one"], &[]] {
+        bob.send(&Message::History { count: 2 }).await;
+        let mut page = Vec::new();
+        loop {
+            match bob.recv().await {
+                Message::Chat { text, .. } => page.push(text),
+                Message::System { text } if text == "end of history for lobby" => break,
+                _ => {}
+            }
+        }
+        assert_eq!(page, expected);
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn logins_are_checked() {
+    let server = TestServer::start().await;
+    let _alice = server.join("alice").await;
+
+    //anything before logging in is refused
+    let mut client = TestClient::connect(server.addr).await;
+    client.chat("hello?").await;
+    assert!(matches!(client.recv().await, Message::Rejected { .. }));
+    assert_eq!(client.try_recv().await, None);
+
+    //a wrong password is refused
+    let mut client = TestClient::connect(server.addr).await;
+    client.send(&Message::Login { name: "alice".to_string(), password: "wrong".to_string(), resume: None }).await;
+    assert_eq!(client.recv().await, Message::Rejected { reason: "invalid name or password".to_string() });
+
+    //the right password is still refused while alice is connected
+    let mut client = TestClient::connect(server.addr).await;
+    client.send(&Message::Login { name: "alice".to_string(), password: "secret".to_string(), resume: None }).await;
+    assert_eq!(client.recv().await, Message::Rejected { reason: "the name alice is already taken".to_string() });
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn guesses_at_the_same_time_share_one_budget() {
+    //slow hashing, so every guess is still being checked when the others come in
+    let server = TestServer::start_with(&["--max-login-failures", "2", "--hash-iterations", "20000"]).await;
+    let _alice = server.join("alice").await;
+
+    let mut clients = Vec::new();
+    for _ in 0..5 {
+        let mut client = TestClient::connect(server.addr).await;
+        client.send(&Message::Login { name: "alice".to_string(), password: "guess".to_string(), resume: None }).await;
+        clients.push(client);
+    }
+    let mut refused = Vec::new();
+    for client in &mut clients {
+        let Message::Rejected { reason } = client.recv().await else { panic!("a guess got in") };
+        refused.push(reason);
+    }
+    assert_eq!(refused.iter().filter(|reason| *reason == "invalid name or password").count(), 2);
+    assert_eq!(refused.iter().filter(|reason| *reason == "too many failed logins, try again later").count(), 3);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn registering_doesnt_reveal_names_or_skip_the_limit() {
+    let server = TestServer::start_with(&["--max-login-failures", "2"]).await;
+    //registering a new name doesn't use up the budget
+    let _alice = server.join("alice").await;
+    let _bob = server.join("bob").await;
+
+    //a taken name is refused like a wrong password, and counts as a failure
+    let register = |name: &str| Message::Register { name: name.to_string(), password: "secret".to_string() };
+    for _ in 0..2 {
+        let mut client = TestClient::connect(server.addr).await;
+        client.send(&register("alice")).await;
+        assert_eq!(client.recv().await, Message::Rejected { reason: "invalid name or password".to_string() });
+    }
+    let mut client = TestClient::connect(server.addr).await;
+    client.send(&register("carol")).await;
+    assert_eq!(client.recv().await, Message::Rejected { reason: "too many failed logins, try again later".to_string() });
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn shutdown_says_goodbye_and_closes_connections() {
+    let server = TestServer::start().await;
+    let mut alice = server.join("alice").await;
+    let mut bob = server.join("bob").await;
+
+    server.stop().await;
+
+    for client in [&mut alice, &mut bob] {
+        client.recv_until(|message| *message == Message::System { text: "the server is shutting down".to_string() }).await;
+        assert_eq!(client.try_recv().await, None);
+    }
+}
+
+#[tokio::test]
+async fn member_lists_follow_joins_and_leaves() {
+    let server = TestServer::start().await;
+    let mut alice = server.join("alice").await;
+    let members = |names: &[&str]| Message::Members {
+        room: "lobby".to_string(),
+        names: names.iter().map(|name| name.to_string()).collect(),
+    };
+
+    let bob = server.join("bob").await;
+    alice.recv_until(|message| *message == members(&["alice", "bob"])).await;
+    drop(bob);
+    alice.recv_until(|message| *message == members(&["alice"])).await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn resuming_replays_only_what_was_missed() {
+    let server = TestServer::start().await;
+    let mut alice = server.join("alice").await;
+    let mut bob = server.join("bob").await;
+    for client in [&mut alice, &mut bob] {
+        client.send(&Message::JoinRoom { room: "dev".to_string() }).await;
+        client.recv_until(|message| *message == Message::System { text: "you joined dev".to_string() }).await;
+    }
+
+    bob.chat("one").await;
+    assert_eq!(alice.recv_chat().await.1, "one");
+    let resume = Resume { room: "dev".to_string(), seq: alice.seq };
+    drop(alice);
+    bob.recv_until(|message| *message == Message::Leave { name: "alice".to_string() }).await;
+    bob.chat("two").await;
+    bob.chat("three").await;
+    assert_eq!(bob.recv_chat().await.1, "two");
+    assert_eq!(bob.recv_chat().await.1, "three");
+
+    let mut alice = TestClient::connect(server.addr).await;
+    let login = Message::Login { name: "alice".to_string(), password: "secret".to_string(), resume: Some(resume) };
+    alice.send(&login).await;
+    assert_eq!(alice.recv().await, Message::Authenticated { name: "alice".to_string() });
+    assert_eq!(alice.recv_chat().await.1, "two");
+    assert_eq!(alice.recv_chat().await.1, "three");
+    alice.recv_until(|message| matches!(message, Message::Members { room, .. } if room == "dev")).await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn resuming_takes_over_a_stale_connection() {
+    let server = TestServer::start().await;
+    let mut stale = server.join("alice").await;
+
+    let mut carol = server.join("carol").await;
+
+    let mut alice = TestClient::connect(server.addr).await;
+    let resume = Resume { room: "lobby".to_string(), seq: stale.seq };
+    alice.send(&Message::Login { name: "alice".to_string(), password: "secret".to_string(), resume: Some(resume) }).await;
+    assert_eq!(alice.recv().await, Message::Authenticated { name: "alice".to_string() });
+
+    let kicked = Message::Rejected { reason: "you logged in from another connection".to_string() };
+    stale.recv_until(|message| *message == kicked).await;
+    assert_eq!(stale.try_recv().await, None);
+
+    //the old connection going away doesn't take the name with it
+    let mut bob = server.join("bob").await;
+    bob.send(&Message::Direct { from: String::new(), to: "alice".to_string(), text: "still there?".to_string() }).await;
+    alice.recv_until(|message| matches!(message, Message::Direct { text, .. } if text == "still there?")).await;
+
+    //nor does anyone hear that alice left or went offline
+    let left = |message: &Message| match message {
+        Message::Leave { name } => name == "alice",
+        Message::Presence { name, status } => name == "alice" && *status == Status::Offline,
+        _ => false,
+    };
+    let mut seen = Vec::new();
+    loop {
+        let message = carol.recv().await;
+        if matches!(&message, Message::Join { name } if name == "bob") {
+            break;
+        }
+        seen.push(message);
+    }
+    assert!(!seen.iter().any(left), "{:?}", seen);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn files_are_relayed_once_accepted() {
+    let server = TestServer::start().await;
+    let mut alice = server.join("alice").await;
+    let mut bob = server.join("bob").await;
+
+    let offer = Message::FileOffer {
+        id: 1,
+        from: String::new(),
+        to: "bob".to_string(),
+        file_name: "notes.txt".to_string(),
+        size: 6,
+        sha256: "abc".to_string(),
+    };
+    alice.send(&offer).await;
+    let offered = bob.recv_until(|message| matches!(message, Message::FileOffer { .. })).await;
+    assert!(matches!(offered, Message::FileOffer { id: 1, from, .. } if from == "alice"));
+
+    //nothing gets through before bob says yes
+    let chunk = |offset: u64, data: &[u8]| Message::FileChunk {
+        id: 1,
+        from: String::new(),
+        to: "bob".to_string(),
+        offset,
+        data: data.to_vec(),
+    };
+    alice.send(&chunk(0, b"early")).await;
+
+    bob.send(&Message::FileAccept { id: 1, from: "alice".to_string(), by: String::new() }).await;
+    let accepted = Message::FileAccept { id: 1, from: "alice".to_string(), by: "bob".to_string() };
+    alice.recv_until(|message| *message == accepted).await;
+
+    alice.send(&chunk(0, b"hel")).await;
+    alice.send(&chunk(3, b"lo!")).await;
+    alice.send(&Message::FileDone { id: 1, from: String::new(), to: "bob".to_string() }).await;
+
+    let relayed = |offset: u64, data: &[u8]| Message::FileChunk {
+        id: 1,
+        from: "alice".to_string(),
+        to: "bob".to_string(),
+        offset,
+        data: data.to_vec(),
+    };
+    let is_file = |message: &Message| matches!(message, Message::FileChunk { .. } | Message::FileDone { .. });
+    assert_eq!(bob.recv_until(is_file).await, relayed(0, b"hel"));
+    //bob's progress goes back to alice while the transfer runs
+    bob.send(&Message::FileAck { id: 1, from: "alice".to_string(), by: String::new(), offset: 3 }).await;
+    let ack = Message::FileAck { id: 1, from: "alice".to_string(), by: "bob".to_string(), offset: 3 };
+    alice.recv_until(|message| *message == ack).await;
+    assert_eq!(bob.recv_until(is_file).await, relayed(3, b"lo!"));
+    assert_eq!(
+        bob.recv_until(is_file).await,
+        Message::FileDone { id: 1, from: "alice".to_string(), to: "bob".to_string() }
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn a_stalled_recipient_doesnt_hold_up_the_sender() {
+    let server = TestServer::start().await;
+    let mut alice = server.join("alice").await;
+    let mut bob = server.join("bob").await;
+    let mut carl = server.join("carl").await;
+
+    let offer = Message::FileOffer {
+        id: 1,
+        from: String::new(),
+        to: "bob".to_string(),
+        file_name: "big".to_string(),
+        size: 1 << 26,
+        sha256: String::new(),
+    };
+    alice.send(&offer).await;
+    bob.recv_until(|message| matches!(message, Message::FileOffer { .. })).await;
+    bob.send(&Message::FileAccept { id: 1, from: "alice".to_string(), by: String::new() }).await;
+    alice.recv_until(|message| matches!(message, Message::FileAccept { .. })).await;
+
+    //bob stops reading, far more than fits in his queue and socket buffers comes at him anyway
+    let sent = tokio::time::timeout(RECV_TIMEOUT, async {
+        for i in 0..256u64 {
+            let data = vec![1; 64 * 1024];
+            alice.send(&Message::FileChunk { id: 1, from: String::new(), to: "bob".to_string(), offset: i * 64 * 1024, data }).await;
+        }
+        alice.chat("still here").await;
+    });
+    sent.await.expect("the server stopped reading alice");
+    assert_eq!(carl.recv_chat().await, ("alice".to_string(), "still here".to_string()));
+
+    //the transfer to bob was given up instead
+    let stopped = Message::FileReject { id: 1, from: "alice".to_string(), by: "bob".to_string() };
+    alice.recv_until(|message| *message == stopped).await;
+    drop(bob);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn presence_changes_reach_everyone() {
+    let server = TestServer::start().await;
+    let mut alice = server.join("alice").await;
+    let presence = |name: &str, status: Status| Message::Presence { name: name.to_string(), status };
+
+    let mut bob = server.join("bob").await;
+    alice.recv_until(|message| *message == presence("bob", Status::Online)).await;
+
+    //going away on purpose sticks while bob keeps chatting
+    bob.send(&Message::SetStatus { status: Status::Away }).await;
+    alice.recv_until(|message| *message == presence("bob", Status::Away)).await;
+    bob.chat("brb").await;
+    bob.send(&Message::SetStatus { status: Status::Online }).await;
+    assert_eq!(alice.recv_chat().await.1, "brb");
+    let next = alice.recv_until(|message| matches!(message, Message::Presence { .. })).await;
+    assert_eq!(next, presence("bob", Status::Online));
+
+    //someone logging in later learns who is away
+    bob.send(&Message::SetStatus { status: Status::Away }).await;
+    alice.recv_until(|message| *message == presence("bob", Status::Away)).await;
+    let mut carl = TestClient::connect(server.addr).await;
+    carl.send(&Message::Register { name: "carl".to_string(), password: "secret".to_string() }).await;
+    carl.recv_until(|message| *message == presence("bob", Status::Away)).await;
+
+    drop(bob);
+    alice.recv_until(|message| *message == presence("bob", Status::Offline)).await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn silent_connections_give_up_their_slot() {
+    let server = TestServer::start_with(&["--max-clients", "1", "--handshake-timeout-secs", "1"]).await;
+
+    //a connection that never logs in is dropped, and the next one gets in
+    let mut silent = TestClient::connect(server.addr).await;
+    assert_eq!(silent.try_recv().await, Some(Message::Rejected { reason: "no login in time".to_string() }));
+    assert_eq!(silent.try_recv().await, None);
+    server.join("alice").await;
+
+    let (_, body) = server.get("/metrics").await;
+    assert!(body.contains("chat_disconnects_total{reason=\"handshake_timeout\"} 1"));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn stalled_tls_and_websocket_handshakes_time_out() {
+    //a self-signed certificate for the server, the test never gets as far as checking it
+    let dir = scratch_dir();
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    std::fs::write(dir.join("cert.pem"), cert.cert.pem()).unwrap();
+    std::fs::write(dir.join("key.pem"), cert.key_pair.serialize_pem()).unwrap();
+    let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+    let flags = ["--handshake-timeout-secs", "1", "--tls-cert", cert.to_str().unwrap(), "--tls-key", key.to_str().unwrap()];
+    let tls = TestServer::start_with(&flags).await;
+    let plain = TestServer::start_with(&["--handshake-timeout-secs", "1"]).await;
+
+    //connections that never send a ClientHello or an upgrade request are closed
+    for addr in [tls.addr, tls.ws_addr, plain.ws_addr] {
+        let mut silent = TcpStream::connect(addr).await.unwrap();
+        let mut rest = Vec::new();
+        let read = tokio::time::timeout(RECV_TIMEOUT, silent.read_to_end(&mut rest)).await;
+        assert!(matches!(read, Ok(Ok(0))), "{} kept a silent connection open", addr);
+    }
+
+    let (_, body) = tls.get("/metrics").await;
+    assert!(body.contains("chat_disconnects_total{reason=\"handshake_timeout\"} 2"), "{}", body);
+    let (_, body) = plain.get("/metrics").await;
+    assert!(body.contains("chat_disconnects_total{reason=\"handshake_timeout\"} 1"), "{}", body);
+
+    tls.stop().await;
+    plain.stop().await;
+}
+
+#[tokio::test]
+async fn admins_can_broadcast_kick_and_ban() {
+    let mut server = TestServer::start().await;
+    let mut alice = server.join("alice").await;
+    let mut bob = server.join("bob").await;
+    let mut carl = server.join("carl").await;
+    alice.send(&Message::JoinRoom { room: "dev".to_string() }).await;
+    alice.recv_until(|message| *message == Message::System { text: "you joined dev".to_string() }).await;
+
+    //a broadcast reaches every room
+    assert_eq!(server.admin("/broadcast back in five").await, "sent to 2 rooms");
+    let notice = Message::System { text: "[admin] back in five".to_string() };
+    for client in [&mut alice, &mut bob, &mut carl] {
+        client.recv_until(|message| *message == notice).await;
+    }
+
+    //a kicked user is told why and disconnected, everyone else stays
+    assert_eq!(server.admin("/kick bob").await, "kicked bob");
+    bob.recv_until(|message| *message == Message::Rejected { reason: "kicked by an admin".to_string() }).await;
+    assert_eq!(bob.try_recv().await, None);
+    carl.chat("still here").await;
+    assert_eq!(carl.recv_chat().await.1, "still here");
+
+    //a ban drops everyone on the address and refuses them when they come back
+    assert_eq!(server.admin("/ban 127.0.0.1").await, "banned 127.0.0.1, disconnected 2 users");
+    for client in [&mut alice, &mut carl] {
+        client.recv_until(|message| *message == Message::Rejected { reason: "banned by an admin".to_string() }).await;
+        assert_eq!(client.try_recv().await, None);
+    }
+    //turned away right after connecting, before it can even try to log in
+    let mut again = TestClient::connect(server.addr).await;
+    assert_eq!(again.recv().await, Message::Rejected { reason: "this address is banned".to_string() });
+    assert_eq!(again.try_recv().await, None);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn idle_users_are_shown_as_away() {
+    let server = TestServer::start_with(&["--idle-secs", "1"]).await;
+    let mut alice = server.join("alice").await;
+    let mut bob = server.join("bob").await;
+    let presence = |status: Status| Message::Presence { name: "bob".to_string(), status };
+
+    alice.recv_until(|message| *message == presence(Status::Away)).await;
+    bob.chat("back").await;
+    alice.recv_until(|message| *message == presence(Status::Online)).await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn typing_and_receipts_are_passed_on() {
+    let server = TestServer::start().await;
+    let mut alice = server.join("alice").await;
+    let mut bob = server.join("bob").await;
+
+    bob.send(&Message::Typing { name: String::new(), room: String::new() }).await;
+    let typing = Message::Typing { name: "bob".to_string(), room: "lobby".to_string() };
+    alice.recv_until(|message| *message == typing).await;
+
+    alice.chat("did you get this?").await;
+    assert_eq!(bob.recv_chat().await.1, "did you get this?");
+    let seq = bob.seq;
+    for read in [false, true] {
+        bob.send(&Message::Receipt { seq, author: "alice".to_string(), by: String::new(), read }).await;
+        let receipt = Message::Receipt { seq, author: "alice".to_string(), by: "bob".to_string(), read };
+        alice.recv_until(|message| *message == receipt).await;
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn browsers_chat_with_tcp_clients() {
+    let server = TestServer::start().await;
+    let mut alice = server.join("alice").await;
+
+    let (mut browser, _) = tokio_tungstenite::connect_async(format!("ws://{}", server.ws_addr)).await.unwrap();
+    let send = |message: Message| WsMessage::Text(serde_json::to_string(&message).unwrap());
+    browser.send(send(Message::Register { name: "bob".to_string(), password: "secret".to_string() })).await.unwrap();
+
+    ws_recv_until(&mut browser, |message| *message == Message::Authenticated { name: "bob".to_string() }).await;
+    alice.recv_until(|message| *message == Message::Join { name: "bob".to_string() }).await;
+
+    alice.chat("hello browser").await;
+    assert_eq!(alice.recv_chat().await.1, "hello browser");
+    let chat = ws_recv_until(&mut browser, |message| matches!(message, Message::Chat { .. })).await;
+    assert_eq!(chat, Message::Chat { name: "alice".to_string(), text: "hello browser".to_string() });
+
+    browser.send(send(Message::Chat { name: String::new(), text: "hello terminal".to_string() })).await.unwrap();
+    assert_eq!(alice.recv_chat().await, ("bob".to_string(), "hello terminal".to_string()));
+
+    browser.close(None).await.unwrap();
+    alice.recv_until(|message| *message == Message::Leave { name: "bob".to_string() }).await;
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn metrics_can_be_scraped() {
+    let server = TestServer::start().await;
+    assert_eq!(server.get("/healthz").await, ("HTTP/1.1 200 OK".to_string(), "ok\n".to_string()));
+    assert_eq!(server.get("/nothing").await.0, "HTTP/1.1 404 Not Found");
+
+    let mut alice = server.join("alice").await;
+    let bob = server.join("bob").await;
+    alice.chat("counted").await;
+    alice.recv_chat().await;
+    drop(bob);
+    alice.recv_until(|message| *message == Message::Leave { name: "bob".to_string() }).await;
+
+    let (status, body) = server.get("/metrics").await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let value = |name: &str| {
+        let line = body.lines().find(|line| line.starts_with(&format!("{} ", name))).unwrap();
+        line.rsplit(' ').next().unwrap().parse::<u64>().unwrap()
+    };
+    assert_eq!(value("chat_connected_clients"), 1);
+    assert_eq!(value("chat_connections_total"), 2);
+    assert_eq!(value("chat_messages_total"), 1);
+    assert!(value("chat_bytes_received_total") > 0);
+    assert!(value("chat_bytes_sent_total") > 0);
+    assert!(body.contains("# TYPE chat_disconnects_total counter"));
+    assert!(body.contains("chat_disconnects_total{reason=\"client_closed\"} 1"));
+
+    server.stop().await;
+}
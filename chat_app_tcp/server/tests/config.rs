@@ -0,0 +1,15 @@
+use server::Config;
+
+#[test]
+fn zero_is_refused_where_it_makes_no_sense() {
+    for flag in ["--max-clients", "--room-capacity", "--max-lag-strikes", "--max-login-failures"] {
+        assert!(Config::try_from_args(["server", flag, "0"]).is_err(), "{} 0 was accepted", flag);
+        assert!(Config::try_from_args(["server", flag, "1"]).is_ok(), "{} 1 was refused", flag);
+    }
+
+    //the environment goes through the same checks as the flags
+    std::env::set_var("CHAT_ROOM_CAPACITY", "0");
+    assert!(Config::try_from_args(["server"]).is_err());
+    std::env::remove_var("CHAT_ROOM_CAPACITY");
+    assert_eq!(Config::try_from_args(["server"]).unwrap().room_capacity, 100);
+}
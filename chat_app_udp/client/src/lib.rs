@@ -0,0 +1,76 @@
+use lib::discovery::{discover, multicast_socket};
+use lib::packet::{ClientId, Packet, ServerPacket};
+use lib::reliable::Endpoint;
+use lib::secure::{Handshake, Opener, Sealer};
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+//how long the server gets to answer our hello
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+//function for listening for server announcements and letting the user pick one of the servers
+//`wait` is how long to listen, `input` hands over the user's lines until there are no more
+pub async fn pick_server<F, Fut>(group: SocketAddrV4, interface: Ipv4Addr, wait: Duration, mut input: F) -> Option<SocketAddr>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<String>>,
+{
+    let socket = match multicast_socket(group, interface) {
+        Ok(socket) => socket,
+        Err(err) => {
+            eprintln!("Failed to join multicast group {}: {}", group, err);
+            return None;
+        }
+    };
+    println!("Looking for servers on {}...", group);
+    let servers = match discover(&socket, wait).await {
+        Ok(servers) => servers,
+        Err(err) => {
+            eprintln!("Failed to listen for servers: {}", err);
+            return None;
+        }
+    };
+    if servers.is_empty() {
+        eprintln!("No servers found");
+        return None;
+    }
+
+    for (i, (addr, announcement)) in servers.iter().enumerate() {
+        println!("{}) {} at {}", i + 1, announcement.name, addr);
+    }
+    loop {
+        println!("Pick a server");
+        let input = input().await?;
+        match input.trim().parse::<usize>() {
+            Ok(n) if (1..=servers.len()).contains(&n) => return Some(servers[n - 1].0),
+            _ => println!("Enter a number from 1 to {}", servers.len()),
+        }
+    }
+}
+
+//function for sending our hello and waiting for the server's
+pub async fn key_exchange(
+    endpoint: &Endpoint<UdpSocket>,
+    deliveries: &mut mpsc::UnboundedReceiver<(SocketAddr, Vec<u8>)>,
+    server_addr: SocketAddr,
+    handshake: Handshake,
+) -> Result<(Sealer, Opener), String> {
+    let id = ClientId::of(&handshake.public_key());
+    let hello = Packet::Hello { key: handshake.public_key() };
+    endpoint.send(server_addr, &hello.encode(id)).await.map_err(|err| format!("Failed to send hello: {}", err))?;
+
+    while let Some((from, payload)) = deliveries.recv().await {
+        if from != server_addr {
+            continue;
+        }
+        match ServerPacket::decode(&payload) {
+            Ok(ServerPacket::Hello { key }) => return Ok(handshake.finish(key, true)),
+            Ok(ServerPacket::Full) => return Err("server is full".to_string()),
+            _ => log::debug!("Ignoring datagram from {} during the handshake", from),
+        }
+    }
+    Err("Endpoint stopped".to_string())
+}
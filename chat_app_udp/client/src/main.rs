@@ -1,51 +1,347 @@
+use clap::{value_parser, Arg, ArgAction, Command};
+use client::{key_exchange, pick_server, HANDSHAKE_TIMEOUT};
+use lib::discovery::DEFAULT_GROUP;
+use lib::packet::{ClientId, Packet, ServerPacket};
+use lib::reliable::{Endpoint, Reliability, MIN_MTU};
+use lib::secure::{Handshake, Opener, Sealer};
+use lib::MAX_DATAGRAM;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
 
+mod p2p;
+
+//how long to listen for server announcements, a few times what servers wait between them by default
+const DISCOVER_WAIT: Duration = Duration::from_secs(5);
+
 #[tokio::main]
 async fn main() {
 
+    // every flag can also come from its environment variable, so the client can be started non-interactively
+    let matches = Command::new("udp chat client")
+        .about("udp chat client")
+        .arg(
+            Arg::new("server")
+                .long("server")
+                .env("CHAT_SERVER")
+                .value_name("ADDR")
+                .help("address of the chat server")
+                .default_value("127.0.0.1:8080"),
+        )
+        .arg(
+            Arg::new("discover")
+                .long("discover")
+                .env("CHAT_DISCOVER")
+                .help("list the servers announcing themselves on the local network and pick one instead")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("p2p"),
+        )
+        .arg(
+            Arg::new("name")
+                .long("name")
+                .env("CHAT_NAME")
+                .value_name("NAME")
+                .help("user name, asked for on stdin when missing"),
+        )
+        .arg(
+            Arg::new("heartbeat")
+                .long("heartbeat")
+                .env("CHAT_HEARTBEAT")
+                .value_name("SECS")
+                .help("seconds between pings to the server or gossip rounds between peers, keep it well under their timeout")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("10"),
+        )
+        .arg(
+            Arg::new("bind")
+                .long("bind")
+                .env("CHAT_BIND")
+                .value_name("ADDR")
+                .help("local address, by default any free port"),
+        )
+        .arg(
+            Arg::new("p2p")
+                .long("p2p")
+                .env("CHAT_P2P")
+                .help("chat with peers directly instead of through a server")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("peers")
+                .long("peers")
+                .env("CHAT_PEERS")
+                .value_name("ADDR,...")
+                .help("peers to contact first in p2p mode, the rest of the group is learned from them")
+                .value_delimiter(','),
+        )
+        .arg(
+            Arg::new("multicast")
+                .long("multicast")
+                .env("CHAT_MULTICAST")
+                .value_name("GROUP:PORT")
+                .help("multicast group on which p2p peers find each other, and on which --discover looks for servers")
+                .value_parser(value_parser!(SocketAddrV4))
+                .default_value(DEFAULT_GROUP),
+        )
+        .arg(
+            Arg::new("multicast-if")
+                .long("multicast-if")
+                .env("CHAT_MULTICAST_IF")
+                .value_name("ADDR")
+                .help("address of the interface to use the multicast group on")
+                .value_parser(value_parser!(Ipv4Addr))
+                .default_value("0.0.0.0"),
+        )
+        .arg(
+            Arg::new("peer-timeout")
+                .long("peer-timeout")
+                .env("CHAT_PEER_TIMEOUT")
+                .value_name("SECS")
+                .help("seconds without a heartbeat after which a p2p peer is dropped")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("30"),
+        )
+        .arg(
+            Arg::new("mtu")
+                .long("mtu")
+                .env("CHAT_MTU")
+                .value_name("BYTES")
+                .help("largest datagram to send, longer messages are split into fragments")
+                .value_parser(value_parser!(u64).range(MIN_MTU as u64..=MAX_DATAGRAM as u64))
+                .default_value("1200"),
+        )
+        .arg(
+            Arg::new("log-level")
+                .long("log-level")
+                .env("CHAT_LOG_LEVEL")
+                .value_name("FILTER")
+                .help("log filter, e.g. warn, info or debug")
+                .default_value("warn"),
+        )
+        .get_matches();
+
+    env_logger::Builder::new().parse_filters(matches.get_one::<String>("log-level").unwrap()).init();
+    let heartbeat = Duration::from_secs(*matches.get_one::<u64>("heartbeat").unwrap());
+    let reliability = Reliability { mtu: *matches.get_one::<u64>("mtu").unwrap() as usize, ..Reliability::default() };
+
+    //finding a server on the local network first, if asked to
+    let interface = *matches.get_one::<Ipv4Addr>("multicast-if").unwrap();
+    let discovered = if matches.get_flag("discover") {
+        let group = *matches.get_one::<SocketAddrV4>("multicast").unwrap();
+        match pick_server(group, interface, DISCOVER_WAIT, read_line).await {
+            Some(addr) => Some(addr),
+            None => return,
+        }
+    } else {
+        None
+    };
+
+    //user input for name, unless it was given up front
+    let name = match matches.get_one::<String>("name") {
+        Some(name) => name.trim().to_string(),
+        None => {
+            println!("Enter your name");
+            let mut name = String::new();
+            std::io::stdin().read_line(&mut name).expect("failed reading name");
+            name.trim().to_string()
+        }
+    };
+
+    let local = matches.get_one::<String>("bind").map(String::as_str);
+    if matches.get_flag("p2p") {
+        let mut seeds = Vec::new();
+        for peer in matches.get_many::<String>("peers").into_iter().flatten() {
+            match tokio::net::lookup_host(peer.as_str()).await.ok().and_then(|mut addrs| addrs.next()) {
+                Some(addr) => seeds.push(addr),
+                None => eprintln!("Failed to resolve peer {}", peer),
+            }
+        }
+        let options = p2p::Options {
+            seeds,
+            multicast: (*matches.get_one::<SocketAddrV4>("multicast").unwrap(), interface),
+            gossip_every: heartbeat,
+            peer_timeout: Duration::from_secs(*matches.get_one::<u64>("peer-timeout").unwrap()),
+        };
+        let socket = UdpSocket::bind(local.unwrap_or("0.0.0.0:0")).await.expect("Failed to bind UDP socket");
+        let (endpoint, deliveries) = Endpoint::start(socket, reliability);
+        println!("Welcome to the chat!!");
+        p2p::run(endpoint, deliveries, name, options).await;
+        return;
+    }
+
     // Binding the UDP socket for the client
-    let server_addr = "127.0.0.1:8080";
-    let client_addr = "127.0.0.1:0"; // Let the system assign a random port for the client
+    let server = matches.get_one::<String>("server").unwrap();
+    let server_addr = match discovered {
+        Some(addr) => addr,
+        None => tokio::net::lookup_host(server.as_str())
+            .await
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .expect("Failed to resolve server address"),
+    };
+    // Let the system assign a random port for the client, on whichever family reaches the server
+    let client_addr = local.unwrap_or(if server_addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" });
 
+    //one socket for everything, so the server sees a single address for us. the endpoint on it is
+    //shared by the receiving and sending tasks and takes care of acks, retransmissions and ordering
     let socket = UdpSocket::bind(client_addr).await.expect("Failed to bind UDP socket");
-    // Binding another UDP socket for the server communicatio
-    let server_socket = UdpSocket::bind("0.0.0.0:0").await.expect("Failed to bind UDP socket");
+    let (endpoint, mut deliveries) = Endpoint::start(socket, reliability);
 
-    //user input for name
-    println!("Enter your name");
-    let mut name = String::new();
-    std::io::stdin().read_line(&mut name).expect("failed reading name");
-    let name = name.trim().to_string();
+    //agreeing on keys with the server before anything else, everything after the hellos is encrypted.
+    //who we are to the server, in every packet we send, follows from our key
+    let handshake = Handshake::new();
+    let id = ClientId::of(&handshake.public_key());
+    let (sealer, opener) = match tokio::time::timeout(HANDSHAKE_TIMEOUT, key_exchange(&endpoint, &mut deliveries, server_addr, handshake)).await {
+        Ok(Ok(keys)) => keys,
+        Ok(Err(err)) => {
+            eprintln!("{}", err);
+            return;
+        }
+        Err(_) => {
+            eprintln!("No answer from {}", server_addr);
+            return;
+        }
+    };
 
     println!("Welcome to the chat!!");
-    let username = name.clone();
+
+    //everything for the server goes through one task, so packets are sealed in the order they are sent
+    let (outgoing, queue) = mpsc::unbounded_channel::<Outgoing>();
+    let sealing = tokio::spawn(seal_and_send(endpoint.clone(), id, server_addr, sealer, queue));
 
     //sending a joining message to the server
-    socket.send_to(format!("{} has joined the chat" , username).as_bytes(), server_addr).await.expect("Failed to send join message");
+    outgoing.send(Outgoing::Packet(Packet::Join { name: name.clone() })).expect("Failed to send join message");
+
+    //pinging the server every so often, so it doesn't take a quiet user for a dead one
+    let heartbeats = tokio::spawn(send_heartbeats(outgoing.clone(), heartbeat));
 
     //creating a channel to handle incoming messages
     let (tx, mut rx) = mpsc::channel::<String>(100);
 
-    //spawning a task to handle incoming messages
-    tokio::spawn(handle_messages(socket, tx));
+    //spawning a task to handle incoming messages, it joins again if the server forgot about us
+    tokio::spawn(handle_messages(endpoint.clone(), server_addr, deliveries, (id, opener), name, outgoing.clone(), tx));
 
     //spawning a task to send messages to the server
-    tokio::spawn(send_messages(name,server_socket, server_addr));
+    let mut sending = tokio::spawn(send_messages(outgoing.clone()));
 
-    //start receiving and printing messages
-    while let Some(msg) = rx.recv().await {
-        println!("{}", msg);
+    //start receiving and printing messages, until stdin is closed
+    loop {
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Some(msg) => println!("{}", msg),
+                None => break,
+            },
+            _ = &mut sending => break,
+        }
     }
+
+    //saying goodbye, so the others don't have to wait for the server to time us out
+    heartbeats.abort();
+    let _ = outgoing.send(Outgoing::Packet(Packet::Leave));
+    drop(outgoing);
+    let _ = sealing.await;
+    let _ = tokio::time::timeout(Duration::from_secs(2), endpoint.flush(server_addr)).await;
 }
 
-//function for handling incoming messages
-async fn handle_messages(socket: UdpSocket, tx: mpsc::Sender<String>) {
-    let mut buf = vec![0; 1024];
+//what goes to the sealing task: packets, or the keys of a new session once we joined again
+enum Outgoing {
+    Packet(Packet),
+    Rekey(ClientId, Sealer),
+}
 
+//function for sealing our packets and sending them to the server, until every sender is gone
+async fn seal_and_send(
+    endpoint: Endpoint<UdpSocket>,
+    mut id: ClientId,
+    server_addr: SocketAddr,
+    mut sealer: Sealer,
+    mut queue: mpsc::UnboundedReceiver<Outgoing>,
+) {
+    while let Some(outgoing) = queue.recv().await {
+        let packet = match outgoing {
+            Outgoing::Packet(packet) => packet,
+            Outgoing::Rekey(new_id, new_sealer) => {
+                (id, sealer) = (new_id, new_sealer);
+                continue;
+            }
+        };
+        if let Err(err) = endpoint.send(server_addr, &packet.seal(id, &mut sealer)).await {
+            log::warn!("Failed to send {:?}: {}", packet, err);
+        }
+    }
+}
+
+//function for letting the server know we are still here
+async fn send_heartbeats(outgoing: mpsc::UnboundedSender<Outgoing>, every: Duration) {
+    let mut tick = tokio::time::interval(every);
+    //the join just went out, no need to ping right away
+    tick.tick().await;
     loop {
-        let (len, _) = socket.recv_from(&mut buf).await.expect("Failed to receive data");
-        let msg = String::from_utf8_lossy(&buf[..len]).into_owned();
+        tick.tick().await;
+        if outgoing.send(Outgoing::Packet(Packet::Ping)).is_err() {
+            break;
+        }
+    }
+}
+
+//function for handling incoming messages
+async fn handle_messages(
+    endpoint: Endpoint<UdpSocket>,
+    server_addr: SocketAddr,
+    mut deliveries: mpsc::UnboundedReceiver<(SocketAddr, Vec<u8>)>,
+    (mut id, mut opener): (ClientId, Opener),
+    name: String,
+    outgoing: mpsc::UnboundedSender<Outgoing>,
+    tx: mpsc::Sender<String>,
+) {
+    while let Some((from, payload)) = deliveries.recv().await {
+        //only lines sealed by the server are shown, anything else could have come from anyone
+        let msg = match ServerPacket::decode(&payload) {
+            Ok(ServerPacket::Sealed { data }) => match ServerPacket::open(&data, &mut opener) {
+                Ok(msg) => msg,
+                Err(err) => {
+                    log::warn!("Dropping message from {}: {}", from, err);
+                    continue;
+                }
+            },
+            //e.g. we were evicted during a network hiccup, so we go through the hello and join again.
+            //replies to packets sealed under an older session are left alone
+            Ok(ServerPacket::NoSession { id: lost }) if lost == id && from == server_addr => {
+                let _ = tx.send("Lost the session with the server, joining again".to_string()).await;
+                let handshake = Handshake::new();
+                let new_id = ClientId::of(&handshake.public_key());
+                let sealer = match tokio::time::timeout(HANDSHAKE_TIMEOUT, key_exchange(&endpoint, &mut deliveries, server_addr, handshake)).await {
+                    Ok(Ok((sealer, new_opener))) => {
+                        opener = new_opener;
+                        sealer
+                    }
+                    Ok(Err(err)) => {
+                        eprintln!("{}", err);
+                        return;
+                    }
+                    Err(_) => {
+                        eprintln!("No answer from {}", server_addr);
+                        return;
+                    }
+                };
+                id = new_id;
+                let join = Outgoing::Packet(Packet::Join { name: name.clone() });
+                if outgoing.send(Outgoing::Rekey(id, sealer)).is_err() || outgoing.send(join).is_err() {
+                    return;
+                }
+                continue;
+            }
+            //others took the last places while we were joining
+            Ok(ServerPacket::Full) if from == server_addr => {
+                eprintln!("server is full");
+                return;
+            }
+            _ => {
+                log::debug!("Ignoring datagram from {}", from);
+                continue;
+            }
+        };
 
         //sending the message to the main loop for printing
         tx.send(msg).await.expect("Failed to send message to main loop");
@@ -53,18 +349,23 @@ async fn handle_messages(socket: UdpSocket, tx: mpsc::Sender<String>) {
 }
 
 //function for sending message
-async fn send_messages(name:String , socket: UdpSocket, server_addr: &str) {
-    let mut input = String::new();
-
-    loop {
-        // Read user input
-        std::io::stdin().read_line(&mut input).expect("Failed to read user input");
-        let msg = format!("{} : {}", name, input);
-
-        // Send the input to the server
-        socket.send_to(msg.trim().as_bytes(), server_addr).await.expect("Failed to send message to server");
-
-        // Clear the input buffer
-        input.clear();
+async fn send_messages(outgoing: mpsc::UnboundedSender<Outgoing>) {
+    // stdin closed, nothing more to send
+    while let Some(input) = read_line().await {
+        // Send the input to the server, which puts our name in front
+        if outgoing.send(Outgoing::Packet(Packet::Chat { text: input.trim().to_string() })).is_err() {
+            break;
+        }
     }
 }
+
+//function for reading a line of user input on the blocking pool, the runtime's workers have datagrams to ack meanwhile
+async fn read_line() -> Option<String> {
+    tokio::task::spawn_blocking(|| {
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input).map(|read| (read > 0).then_some(input))
+    })
+    .await
+    .expect("stdin reader panicked")
+    .expect("Failed to read user input")
+}
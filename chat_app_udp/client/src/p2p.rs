@@ -0,0 +1,229 @@
+use lib::discovery::{multicast_socket, Announcement, Role};
+use lib::gossip::{Gossip, Member, Membership, MessageId, PeerId, Seen};
+use lib::reliable::Endpoint;
+use rand::seq::IteratorRandom;
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+//how many members get our view each round, and each chat message we pass on
+const FANOUT: usize = 3;
+//how many chat messages are remembered, so copies coming back through other peers are dropped
+const SEEN: usize = 4096;
+//how long our goodbye gets to reach everyone
+const LEAVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct Options {
+    //peers to reach out to until they answer, the rest of the group is learned from them
+    pub seeds: Vec<SocketAddr>,
+    //group and interface to announce ourselves and find others on
+    pub multicast: (SocketAddrV4, Ipv4Addr),
+    pub gossip_every: Duration,
+    pub peer_timeout: Duration,
+}
+
+// one member of a serverless chat. it keeps a view of the group up to date by gossiping it to
+// a few members every round, and sends what the user types to every member it knows about
+struct Peer {
+    endpoint: Endpoint<UdpSocket>,
+    me: Member,
+    membership: Membership,
+    //addresses we were told about but haven't heard from yet
+    contacts: HashSet<SocketAddr>,
+    seen: Seen,
+    next_seq: u64,
+}
+
+// chatting with peers directly until stdin is closed
+pub async fn run(endpoint: Endpoint<UdpSocket>, mut deliveries: mpsc::UnboundedReceiver<(SocketAddr, Vec<u8>)>, name: String, options: Options) {
+    let addr = endpoint.transport().local_addr().expect("Failed to get local address");
+    let id = PeerId::random();
+    let mut peer = Peer {
+        endpoint,
+        me: Member { id, name: name.clone(), addr, heartbeat: 0 },
+        membership: Membership::new(id),
+        contacts: options.seeds.into_iter().collect(),
+        seen: Seen::new(SEEN),
+        next_seq: 0,
+    };
+
+    let (group, interface) = options.multicast;
+    let multicast = match multicast_socket(group, interface) {
+        Ok(socket) => Some((socket, group)),
+        //the seeds are all there is to go on then, without them we would sit alone
+        Err(err) if peer.contacts.is_empty() => {
+            eprintln!("Failed to join multicast group {} and no peers to start from: {}", group, err);
+            return;
+        }
+        Err(err) => {
+            eprintln!("Failed to join multicast group {}, only reaching out to the given peers: {}", group, err);
+            None
+        }
+    };
+    let announcement = Announcement { role: Role::Peer, id: id.0, port: addr.port(), name }.encode();
+
+    //reading user input in its own task, so the loop below keeps gossiping meanwhile
+    let (lines_tx, mut lines) = mpsc::channel::<String>(100);
+    tokio::spawn(async move {
+        while let Some(line) = super::read_line().await {
+            if lines_tx.send(line).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut tick = tokio::time::interval(options.gossip_every);
+    let mut buf = [0; 1024];
+    loop {
+        tokio::select! {
+            _ = tick.tick() => {
+                peer.gossip(options.peer_timeout).await;
+                if let Some((socket, group)) = &multicast {
+                    if let Err(err) = socket.send_to(&announcement, *group).await {
+                        log::warn!("Failed to announce ourselves on {}: {}", group, err);
+                    }
+                }
+            }
+            delivery = deliveries.recv() => match delivery {
+                Some((from, payload)) => peer.receive(from, &payload).await,
+                None => break,
+            },
+            line = lines.recv() => match line {
+                Some(line) => peer.chat(line.trim()).await,
+                // stdin closed, time to say goodbye
+                None => break,
+            },
+            received = recv_announcement(&multicast, &mut buf) => match received {
+                Ok((len, from)) => peer.discovered(from, &buf[..len]),
+                Err(err) => log::warn!("Failed to receive from the multicast group: {}", err),
+            },
+        }
+    }
+
+    peer.leave().await;
+}
+
+//function for receiving from the multicast group, or waiting forever when there is none
+async fn recv_announcement(multicast: &Option<(UdpSocket, SocketAddrV4)>, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+    match multicast {
+        Some((socket, _)) => socket.recv_from(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
+impl Peer {
+    //one round: dropping the silent, then sending our view to a few members and everyone not yet heard from
+    async fn gossip(&mut self, timeout: Duration) {
+        for member in self.membership.expire(timeout) {
+            println!("{} has left the chat (timed out)", member.name);
+        }
+
+        self.me.heartbeat += 1;
+        let mut targets: HashSet<SocketAddr> = self.membership.members().map(|member| member.addr).choose_multiple(&mut rand::thread_rng(), FANOUT).into_iter().collect();
+        targets.extend(self.contacts.iter().copied());
+        let view = self.view();
+        for addr in targets {
+            self.send(addr, &view).await;
+        }
+    }
+
+    //our own entry first, then everyone we know
+    fn view(&self) -> Gossip {
+        let members = std::iter::once(self.me.clone()).chain(self.membership.members().cloned()).collect();
+        Gossip::Members { members }
+    }
+
+    async fn receive(&mut self, from: SocketAddr, payload: &[u8]) {
+        let gossip = match Gossip::decode(payload) {
+            Ok(gossip) => gossip,
+            Err(err) => {
+                log::debug!("Ignoring datagram from {}: {}", from, err);
+                return;
+            }
+        };
+        match gossip {
+            Gossip::Members { members } => {
+                self.contacts.remove(&from);
+                let sender = members.first().map(|member| member.id);
+                for member in self.membership.merge(from, members) {
+                    println!("{} has joined the chat", member.name);
+                    //someone new reached out to us, they get our view right away instead of next round
+                    if Some(member.id) == sender {
+                        let view = self.view();
+                        self.send(from, &view).await;
+                    }
+                }
+            }
+            Gossip::Chat { id, name, text } => {
+                if !self.seen.insert(id) {
+                    return;
+                }
+                println!("{} : {}", name, text);
+                //passing it on, for members the author doesn't know about yet
+                let forward = Gossip::Chat { id, name, text };
+                let targets: Vec<SocketAddr> = self
+                    .membership
+                    .members()
+                    .filter(|member| member.addr != from && member.id != id.origin)
+                    .map(|member| member.addr)
+                    .choose_multiple(&mut rand::thread_rng(), FANOUT);
+                for addr in targets {
+                    self.send(addr, &forward).await;
+                }
+            }
+            Gossip::Leave { id } => {
+                if let Some(member) = self.membership.leave(id) {
+                    println!("{} has left the chat", member.name);
+                }
+            }
+        }
+    }
+
+    //something showed up on the multicast group, other peers there become contacts
+    fn discovered(&mut self, from: SocketAddr, datagram: &[u8]) {
+        match Announcement::decode(datagram) {
+            Some(announcement) if announcement.role == Role::Peer && announcement.id != self.me.id.0 => {
+                let addr = SocketAddr::new(from.ip(), announcement.port);
+                if !self.membership.members().any(|member| member.addr == addr) && self.contacts.insert(addr) {
+                    log::info!("Found {} at {}", announcement.name, addr);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    //sending what the user typed to every member we know about
+    async fn chat(&mut self, text: &str) {
+        self.next_seq += 1;
+        let id = MessageId { origin: self.me.id, seq: self.next_seq };
+        self.seen.insert(id);
+        let message = Gossip::Chat { id, name: self.me.name.clone(), text: text.to_string() };
+        let targets: Vec<SocketAddr> = self.membership.members().map(|member| member.addr).collect();
+        for addr in targets {
+            self.send(addr, &message).await;
+        }
+    }
+
+    //saying goodbye, so the others don't have to wait for us to time out
+    async fn leave(&mut self) {
+        let goodbye = Gossip::Leave { id: self.me.id };
+        let targets: Vec<SocketAddr> = self.membership.members().map(|member| member.addr).collect();
+        for addr in &targets {
+            self.send(*addr, &goodbye).await;
+        }
+        let _ = tokio::time::timeout(LEAVE_TIMEOUT, async {
+            for addr in targets {
+                self.endpoint.flush(addr).await;
+            }
+        })
+        .await;
+    }
+
+    async fn send(&self, to: SocketAddr, gossip: &Gossip) {
+        if let Err(err) = self.endpoint.send(to, &gossip.encode()).await {
+            log::warn!("Failed to send to {}: {}", to, err);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use client::{key_exchange, pick_server};
+use lib::discovery::{announce, multicast_socket, Announcement, Role};
+use lib::packet::{ClientId, Packet, ServerPacket};
+use lib::reliable::{Endpoint, Reliability};
+use lib::secure::Handshake;
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::test]
+async fn a_discovered_server_can_be_joined() {
+    //a server that announces itself on the loopback interface, the way the real one does
+    let group = SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, 4), 47_105);
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server_addr = socket.local_addr().unwrap();
+    let (server, mut server_rx) = Endpoint::start(socket, Reliability::default());
+    let announcer = multicast_socket(group, Ipv4Addr::LOCALHOST).unwrap();
+    let announcement = Announcement { role: Role::Server, id: 1, port: server_addr.port(), name: "lobby".to_string() };
+    tokio::spawn(async move { announce(&announcer, group, &announcement, Duration::from_millis(100)).await });
+
+    //the user needs a couple of tries to pick the one server on the list
+    let lines = Arc::new(Mutex::new(VecDeque::from(["first", "2", " 1 "].map(String::from))));
+    let input = move || {
+        let line = lines.lock().unwrap().pop_front();
+        async move { line }
+    };
+    let picked = pick_server(group, Ipv4Addr::LOCALHOST, Duration::from_secs(1), input).await;
+    assert_eq!(picked, Some(server_addr));
+
+    //and the handshake goes through on the address that came out of the announcement
+    let (client, mut client_rx) = Endpoint::start(UdpSocket::bind("127.0.0.1:0").await.unwrap(), Reliability::default());
+    let serving = tokio::spawn(async move {
+        let (from, payload) = server_rx.recv().await.unwrap();
+        let Ok((id, Packet::Hello { key })) = Packet::decode(&payload) else { panic!("not a hello") };
+        let handshake = Handshake::new();
+        server.send(from, &ServerPacket::Hello { key: handshake.public_key() }.encode()).await.unwrap();
+        let (_, mut opener) = handshake.finish(key, false);
+        let (_, payload) = server_rx.recv().await.unwrap();
+        let Ok((_, Packet::Sealed { data })) = Packet::decode(&payload) else { panic!("not sealed") };
+        Packet::open(id, &data, &mut opener).unwrap()
+    });
+    let handshake = Handshake::new();
+    let id = ClientId::of(&handshake.public_key());
+    let (mut sealer, _) = tokio::time::timeout(TIMEOUT, key_exchange(&client, &mut client_rx, server_addr, handshake)).await.unwrap().unwrap();
+    client.send(server_addr, &Packet::Join { name: "alice".to_string() }.seal(id, &mut sealer)).await.unwrap();
+    assert_eq!(tokio::time::timeout(TIMEOUT, serving).await.unwrap().unwrap(), Packet::Join { name: "alice".to_string() });
+}
+
+#[tokio::test]
+async fn nothing_is_picked_without_servers() {
+    let group = SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, 5), 47_106);
+    let picked = pick_server(group, Ipv4Addr::LOCALHOST, Duration::from_millis(300), || async { Some("1".to_string()) }).await;
+    assert_eq!(picked, None);
+}
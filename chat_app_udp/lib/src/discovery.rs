@@ -0,0 +1,107 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+
+//where servers announce themselves unless told otherwise
+pub const DEFAULT_GROUP: &str = "239.255.42.99:7645";
+
+// a socket that receives what is sent to the multicast `group` on `interface`, and sends there too.
+// several processes on one host can listen on the same group, and each hears its own announcements
+pub fn multicast_socket(group: SocketAddrV4, interface: Ipv4Addr) -> io::Result<UdpSocket> {
+    if !group.ip().is_multicast() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a multicast address", group.ip())));
+    }
+
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, group.port())).into())?;
+    socket.join_multicast_v4(group.ip(), &interface)?;
+    socket.set_multicast_if_v4(&interface)?;
+    socket.set_multicast_loop_v4(true)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+//first byte of an announcement, then who is announcing
+const ANNOUNCE: u8 = b'A';
+const PEER: u8 = b'P';
+const SERVER: u8 = b'S';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    //a client in serverless mode
+    Peer,
+    Server,
+}
+
+// what goes out on the multicast group every so often: who is there and which port to reach them on.
+// the address is whatever the announcement came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    pub role: Role,
+    //picked at random on start, so an announcer can tell its own announcements apart
+    pub id: u64,
+    pub port: u16,
+    pub name: String,
+}
+
+impl Announcement {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = vec![ANNOUNCE, if self.role == Role::Peer { PEER } else { SERVER }];
+        encoded.extend_from_slice(&self.id.to_be_bytes());
+        encoded.extend_from_slice(&self.port.to_be_bytes());
+        encoded.extend_from_slice(self.name.as_bytes());
+        encoded
+    }
+
+    //anything else that shows up on the group is None
+    pub fn decode(datagram: &[u8]) -> Option<Announcement> {
+        if datagram.len() < 12 || datagram[0] != ANNOUNCE {
+            return None;
+        }
+        let role = match datagram[1] {
+            PEER => Role::Peer,
+            SERVER => Role::Server,
+            _ => return None,
+        };
+        let id = u64::from_be_bytes(datagram[2..10].try_into().ok()?);
+        let port = u16::from_be_bytes(datagram[10..12].try_into().ok()?);
+        let name = std::str::from_utf8(&datagram[12..]).ok()?.to_string();
+        Some(Announcement { role, id, port, name })
+    }
+}
+
+// sending `announcement` to `group` every `every`, until sending fails
+pub async fn announce(socket: &UdpSocket, group: SocketAddrV4, announcement: &Announcement, every: Duration) -> io::Result<()> {
+    let encoded = announcement.encode();
+    let mut tick = tokio::time::interval(every);
+    loop {
+        tick.tick().await;
+        socket.send_to(&encoded, group).await?;
+    }
+}
+
+// listening on the group for `wait`, returning each server heard from once, in the order they showed up.
+// a server is reachable on the announced port of whatever address the announcement came from
+pub async fn discover(socket: &UdpSocket, wait: Duration) -> io::Result<Vec<(SocketAddr, Announcement)>> {
+    let deadline = Instant::now() + wait;
+    let mut found = Vec::new();
+    let mut ids = HashSet::new();
+    let mut buf = [0; 1024];
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (len, from) = received?;
+        match Announcement::decode(&buf[..len]) {
+            Some(announcement) if announcement.role == Role::Server && ids.insert(announcement.id) => {
+                found.push((SocketAddr::new(from.ip(), announcement.port), announcement));
+            }
+            _ => {}
+        }
+    }
+    Ok(found)
+}
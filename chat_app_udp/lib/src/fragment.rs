@@ -0,0 +1,92 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+//index and count in front of every fragment
+pub const HEADER_LEN: usize = 2 + 2;
+
+// cutting a payload into numbered fragments of at most `max_chunk` bytes each.
+// an empty payload still takes one fragment, so it arrives like any other
+pub fn split(payload: &[u8], max_chunk: usize) -> Vec<Vec<u8>> {
+    let chunks: Vec<&[u8]> = if payload.is_empty() { vec![payload] } else { payload.chunks(max_chunk.max(1)).collect() };
+    let count = u16::try_from(chunks.len()).expect("payload needs more than u16::MAX fragments");
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut fragment = Vec::with_capacity(HEADER_LEN + chunk.len());
+            fragment.extend_from_slice(&(index as u16).to_be_bytes());
+            fragment.extend_from_slice(&count.to_be_bytes());
+            fragment.extend_from_slice(chunk);
+            fragment
+        })
+        .collect()
+}
+
+//a set of fragments that isn't complete yet
+struct Partial {
+    count: u16,
+    next: u16,
+    data: Vec<u8>,
+    started: Instant,
+}
+
+// putting one peer's fragments back together. they come in the order they were sent, so a set is
+// collected front to back and anything that doesn't continue it throws the incomplete set away
+#[derive(Default)]
+pub struct Reassembly {
+    partial: Option<Partial>,
+}
+
+impl Reassembly {
+    //adding the next fragment, returning the payload once its last fragment is in.
+    //sets growing past `max_len` are dropped instead of being held on to
+    pub fn push(&mut self, fragment: &[u8], max_len: usize) -> Option<Vec<u8>> {
+        if fragment.len() < HEADER_LEN {
+            log::debug!("Ignoring fragment without a header");
+            return None;
+        }
+        let index = u16::from_be_bytes([fragment[0], fragment[1]]);
+        let count = u16::from_be_bytes([fragment[2], fragment[3]]);
+        let chunk = &fragment[HEADER_LEN..];
+        if index >= count {
+            log::debug!("Ignoring fragment {} of {}", index, count);
+            return None;
+        }
+
+        if index == 0 {
+            if let Some(partial) = self.partial.take() {
+                log::debug!("Discarding incomplete message, got {} of {} fragments", partial.next, partial.count);
+            }
+            self.partial = Some(Partial { count, next: 0, data: Vec::new(), started: Instant::now() });
+        }
+        let Some(partial) = self.partial.as_mut() else {
+            log::debug!("Ignoring fragment {} of {} without its start", index, count);
+            return None;
+        };
+        if partial.count != count || partial.next != index || partial.data.len() + chunk.len() > max_len {
+            log::debug!("Discarding message after fragment {} of {} didn't fit", index, count);
+            self.partial = None;
+            return None;
+        }
+
+        partial.data.extend_from_slice(chunk);
+        partial.next += 1;
+        if partial.next < partial.count {
+            return None;
+        }
+        self.partial.take().map(|partial| partial.data)
+    }
+
+    //dropping a set that has been waiting for its remaining fragments longer than `timeout`
+    pub fn expire(&mut self, timeout: Duration) {
+        if self.partial.as_ref().is_some_and(|partial| partial.started.elapsed() > timeout) {
+            if let Some(partial) = self.partial.take() {
+                log::debug!("Discarding incomplete message, got {} of {} fragments in time", partial.next, partial.count);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.partial = None;
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+//first byte of every gossip payload
+const MEMBERS: u8 = b'M';
+const CHAT: u8 = b'C';
+const LEAVE: u8 = b'X';
+
+//how long a peer that left or timed out is remembered, so stale gossip doesn't bring it back
+const TOMBSTONE_FACTOR: u32 = 3;
+
+// one peer of a serverless chat, picked at random when it starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PeerId(pub u64);
+
+impl PeerId {
+    pub fn random() -> PeerId {
+        PeerId(rand::random())
+    }
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+// a chat message, unique across the group: who wrote it and how many messages they wrote before
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessageId {
+    pub origin: PeerId,
+    pub seq: u64,
+}
+
+// what one peer knows about another. the heartbeat only ever grows while the peer is alive,
+// the highest one seen wins when two views are merged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub id: PeerId,
+    pub name: String,
+    pub addr: SocketAddr,
+    pub heartbeat: u64,
+}
+
+// what peers send each other, each as the payload of one reliable datagram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Gossip {
+    //the sender's view of the group with its own entry first. its address is whatever the
+    //datagram came from, the one in the entry is ignored
+    Members { members: Vec<Member> },
+    Chat { id: MessageId, name: String, text: String },
+    Leave { id: PeerId },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidGossip;
+
+impl fmt::Display for InvalidGossip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid gossip")
+    }
+}
+
+impl std::error::Error for InvalidGossip {}
+
+impl Gossip {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            Gossip::Members { members } => {
+                out.push(MEMBERS);
+                out.extend_from_slice(&(members.len() as u16).to_be_bytes());
+                for member in members {
+                    out.extend_from_slice(&member.id.0.to_be_bytes());
+                    out.extend_from_slice(&member.heartbeat.to_be_bytes());
+                    put_str(&mut out, &member.name);
+                    put_str(&mut out, &member.addr.to_string());
+                }
+            }
+            Gossip::Chat { id, name, text } => {
+                out.push(CHAT);
+                out.extend_from_slice(&id.origin.0.to_be_bytes());
+                out.extend_from_slice(&id.seq.to_be_bytes());
+                put_str(&mut out, name);
+                out.extend_from_slice(text.as_bytes());
+            }
+            Gossip::Leave { id } => {
+                out.push(LEAVE);
+                out.extend_from_slice(&id.0.to_be_bytes());
+            }
+        }
+        out
+    }
+
+    pub fn decode(payload: &[u8]) -> Result<Gossip, InvalidGossip> {
+        let (&kind, rest) = payload.split_first().ok_or(InvalidGossip)?;
+        let mut reader = Reader(rest);
+        let gossip = match kind {
+            MEMBERS => {
+                let count = reader.u16()?;
+                let mut members = Vec::with_capacity(count.into());
+                for _ in 0..count {
+                    let id = PeerId(reader.u64()?);
+                    let heartbeat = reader.u64()?;
+                    let name = reader.str()?;
+                    let addr = reader.str()?.parse().map_err(|_| InvalidGossip)?;
+                    members.push(Member { id, name, addr, heartbeat });
+                }
+                Gossip::Members { members }
+            }
+            CHAT => {
+                let id = MessageId { origin: PeerId(reader.u64()?), seq: reader.u64()? };
+                let name = reader.str()?;
+                let text = String::from_utf8(std::mem::take(&mut reader.0).to_vec()).map_err(|_| InvalidGossip)?;
+                Gossip::Chat { id, name, text }
+            }
+            LEAVE => Gossip::Leave { id: PeerId(reader.u64()?) },
+            _ => return Err(InvalidGossip),
+        };
+        if reader.0.is_empty() {
+            Ok(gossip)
+        } else {
+            Err(InvalidGossip)
+        }
+    }
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    let bytes = &s.as_bytes()[..s.len().min(u16::MAX as usize)];
+    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], InvalidGossip> {
+        if self.0.len() < len {
+            return Err(InvalidGossip);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> Result<u16, InvalidGossip> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().map_err(|_| InvalidGossip)?))
+    }
+
+    fn u64(&mut self) -> Result<u64, InvalidGossip> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().map_err(|_| InvalidGossip)?))
+    }
+
+    fn str(&mut self) -> Result<String, InvalidGossip> {
+        let len = self.u16()?.into();
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| InvalidGossip)
+    }
+}
+
+//a member and when its heartbeat last went up
+struct Known {
+    member: Member,
+    updated: Instant,
+}
+
+// the group as one peer sees it. views from other peers are merged in, and members whose
+// heartbeat stops going up are dropped after a timeout
+pub struct Membership {
+    me: PeerId,
+    members: HashMap<PeerId, Known>,
+    //peers that left or timed out, with the heartbeat they had, only a higher one brings them back
+    dead: HashMap<PeerId, (u64, Instant)>,
+}
+
+impl Membership {
+    pub fn new(me: PeerId) -> Membership {
+        Membership { me, members: HashMap::new(), dead: HashMap::new() }
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = &Member> {
+        self.members.values().map(|known| &known.member)
+    }
+
+    pub fn get(&self, id: PeerId) -> Option<&Member> {
+        self.members.get(&id).map(|known| &known.member)
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    // merging a view that came from `from`, returning the members that are new to us.
+    // the first entry is the sender itself, which is reachable wherever its datagram came from
+    pub fn merge(&mut self, from: SocketAddr, members: Vec<Member>) -> Vec<Member> {
+        let mut joined = Vec::new();
+        for (i, mut member) in members.into_iter().enumerate() {
+            if i == 0 {
+                member.addr = from;
+            }
+            if member.id == self.me {
+                continue;
+            }
+            if self.dead.get(&member.id).is_some_and(|(heartbeat, _)| member.heartbeat <= *heartbeat) {
+                continue;
+            }
+            match self.members.get_mut(&member.id) {
+                Some(known) if member.heartbeat > known.member.heartbeat => {
+                    //only a peer's own word moves it to a new address, second hand ones may be stale
+                    if i != 0 {
+                        member.addr = known.member.addr;
+                    }
+                    known.member = member;
+                    known.updated = Instant::now();
+                }
+                Some(known) => {
+                    if i == 0 {
+                        known.member.addr = from;
+                    }
+                }
+                None => {
+                    self.dead.remove(&member.id);
+                    joined.push(member.clone());
+                    self.members.insert(member.id, Known { member, updated: Instant::now() });
+                }
+            }
+        }
+        joined
+    }
+
+    //a peer saying goodbye, it stays gone whatever stale gossip says
+    pub fn leave(&mut self, id: PeerId) -> Option<Member> {
+        let known = self.members.remove(&id)?;
+        self.dead.insert(id, (u64::MAX, Instant::now()));
+        Some(known.member)
+    }
+
+    //dropping members whose heartbeat hasn't gone up within `timeout`, returning them
+    pub fn expire(&mut self, timeout: Duration) -> Vec<Member> {
+        self.dead.retain(|_, (_, since)| since.elapsed() <= timeout * TOMBSTONE_FACTOR);
+        let silent: Vec<PeerId> = self.members.iter().filter(|(_, known)| known.updated.elapsed() > timeout).map(|(id, _)| *id).collect();
+        silent
+            .into_iter()
+            .filter_map(|id| self.members.remove(&id))
+            .map(|known| {
+                self.dead.insert(known.member.id, (known.member.heartbeat, Instant::now()));
+                known.member
+            })
+            .collect()
+    }
+}
+
+// the chat messages a peer has already seen, so the copies gossip brings back are dropped.
+// only the most recent `capacity` are remembered
+pub struct Seen {
+    ids: HashSet<MessageId>,
+    order: VecDeque<MessageId>,
+    capacity: usize,
+}
+
+impl Seen {
+    pub fn new(capacity: usize) -> Seen {
+        Seen { ids: HashSet::new(), order: VecDeque::new(), capacity: capacity.max(1) }
+    }
+
+    //true the first time an id comes up
+    pub fn insert(&mut self, id: MessageId) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
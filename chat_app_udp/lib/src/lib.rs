@@ -0,0 +1,35 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+
+pub mod discovery;
+pub mod fragment;
+pub mod gossip;
+pub mod limit;
+pub mod packet;
+pub mod reliable;
+pub mod secure;
+
+//largest payload a single udp datagram can carry
+pub const MAX_DATAGRAM: usize = 65_507;
+
+//largest payload the reliable endpoint takes, it goes out in as many fragments as needed
+pub const MAX_MESSAGE: usize = 1024 * 1024;
+
+// anything that can send and receive datagrams. the chat runs on a udp socket,
+// tests put a shim in between that loses and reorders packets
+pub trait Transport: Send + Sync + 'static {
+    fn send_to(&self, datagram: &[u8], to: SocketAddr) -> impl Future<Output = io::Result<usize>> + Send;
+    fn recv_from(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<(usize, SocketAddr)>> + Send;
+}
+
+impl Transport for UdpSocket {
+    async fn send_to(&self, datagram: &[u8], to: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, datagram, to).await
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf).await
+    }
+}
@@ -0,0 +1,255 @@
+use crate::packet::ClientId;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+//how long a sender's strikes add up before they are forgotten
+const STRIKE_WINDOW: Duration = Duration::from_secs(10);
+
+// how fast packets may come in: `per_second` on average, up to `burst` of them at once
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+// what the server puts up with, from one address, from one client and from everyone together
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub per_address: Rate,
+    pub per_client: Rate,
+    pub global: Rate,
+    //packets over its limit a sender gets within the strike window before it is banned, 0 never bans
+    pub ban_after: u32,
+    pub ban_for: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            //clients behind one nat share an address, so it gets more room than a single client
+            //counted in datagrams, a long message takes one for each of its fragments
+            per_address: Rate { per_second: 500.0, burst: 1000.0 },
+            per_client: Rate { per_second: 10.0, burst: 20.0 },
+            global: Rate { per_second: 20000.0, burst: 40000.0 },
+            ban_after: 50,
+            ban_for: Duration::from_secs(60),
+        }
+    }
+}
+
+// a bucket that fills up at a steady rate up to its burst, every packet takes a token out of it
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: Rate,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    //starting out full, so a new sender can send its burst right away
+    pub fn new(rate: Rate) -> TokenBucket {
+        TokenBucket { rate, tokens: rate.burst, updated: Instant::now() }
+    }
+
+    //true when there was a token to take
+    pub fn take(&mut self) -> bool {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.rate.burst
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate.per_second).min(self.rate.burst);
+        self.updated = now;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    Drop,
+    //dropped, and the sender has just been banned for it
+    Ban,
+}
+
+// how many packets were dropped, by what dropped them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Dropped {
+    pub address: u64,
+    pub client: u64,
+    pub global: u64,
+    pub banned: u64,
+}
+
+impl Dropped {
+    pub fn total(&self) -> u64 {
+        self.address + self.client + self.global + self.banned
+    }
+}
+
+impl fmt::Display for Dropped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} over the address limit, {} over the client limit, {} over the global budget, {} from banned senders", self.address, self.client, self.global, self.banned)
+    }
+}
+
+//one address or client, as far as the limits are concerned
+struct Sender {
+    bucket: TokenBucket,
+    strikes: u32,
+    //when the current count of strikes started
+    first_strike: Option<Instant>,
+    banned_until: Option<Instant>,
+}
+
+impl Sender {
+    fn is_banned(&mut self) -> bool {
+        match self.banned_until {
+            Some(until) if until > Instant::now() => true,
+            Some(_) => {
+                self.banned_until = None;
+                self.strikes = 0;
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn strike(&mut self, ban_after: u32, ban_for: Duration) -> Verdict {
+        if !self.striking() {
+            self.strikes = 0;
+            self.first_strike = Some(Instant::now());
+        }
+        self.strikes += 1;
+        if ban_after > 0 && self.strikes >= ban_after {
+            self.banned_until = Some(Instant::now() + ban_for);
+            Verdict::Ban
+        } else {
+            Verdict::Drop
+        }
+    }
+
+    //whether strikes still add up
+    fn striking(&self) -> bool {
+        self.first_strike.is_some_and(|first| first.elapsed() <= STRIKE_WINDOW)
+    }
+
+    fn is_idle(&mut self) -> bool {
+        !self.is_banned() && !self.striking() && self.bucket.is_full()
+    }
+}
+
+//everyone of one kind the limiter has heard from lately
+struct Senders<K> {
+    rate: Rate,
+    senders: HashMap<K, Sender>,
+}
+
+impl<K: Hash + Eq> Senders<K> {
+    fn new(rate: Rate) -> Senders<K> {
+        Senders { rate, senders: HashMap::new() }
+    }
+
+    fn get(&mut self, key: K) -> &mut Sender {
+        let rate = self.rate;
+        self.senders.entry(key).or_insert_with(|| Sender {
+            bucket: TokenBucket::new(rate),
+            strikes: 0,
+            first_strike: None,
+            banned_until: None,
+        })
+    }
+
+    fn prune(&mut self) {
+        self.senders.retain(|_, sender| !sender.is_idle());
+    }
+}
+
+// deciding which packets the server looks at. every data datagram is checked against its source address
+// and the global budget as soon as it arrives, and each packet against the client it belongs to once that is known.
+// senders that keep going over their limit are banned for a while
+pub struct Limiter {
+    limits: Limits,
+    global: TokenBucket,
+    addresses: Senders<IpAddr>,
+    clients: Senders<ClientId>,
+    dropped: Dropped,
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Limiter {
+        Limiter {
+            limits,
+            global: TokenBucket::new(limits.global),
+            addresses: Senders::new(limits.per_address),
+            clients: Senders::new(limits.per_client),
+            dropped: Dropped::default(),
+        }
+    }
+
+    //a datagram from `addr`. an address over its limit doesn't eat into the global budget
+    pub fn check_address(&mut self, addr: IpAddr) -> Verdict {
+        let sender = self.addresses.get(addr);
+        if sender.is_banned() {
+            self.dropped.banned += 1;
+            return Verdict::Drop;
+        }
+        if !sender.bucket.take() {
+            self.dropped.address += 1;
+            return sender.strike(self.limits.ban_after, self.limits.ban_for);
+        }
+        if !self.global.take() {
+            self.dropped.global += 1;
+            return Verdict::Drop;
+        }
+        Verdict::Pass
+    }
+
+    //a packet from client `id`, once it has been authenticated
+    pub fn check_client(&mut self, id: ClientId) -> Verdict {
+        let sender = self.clients.get(id);
+        if sender.is_banned() {
+            self.dropped.banned += 1;
+            return Verdict::Drop;
+        }
+        if !sender.bucket.take() {
+            self.dropped.client += 1;
+            return sender.strike(self.limits.ban_after, self.limits.ban_for);
+        }
+        Verdict::Pass
+    }
+
+    pub fn dropped(&self) -> Dropped {
+        self.dropped
+    }
+
+    //forgetting senders that have been behaving for a while, so the tables don't grow with every address ever seen
+    pub fn prune(&mut self) {
+        self.addresses.prune();
+        self.clients.prune();
+    }
+
+    //how many senders are being tracked
+    pub fn len(&self) -> usize {
+        self.addresses.senders.len() + self.clients.senders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
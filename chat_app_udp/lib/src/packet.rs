@@ -0,0 +1,168 @@
+use crate::secure::{Opener, SecureError, Sealer};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+//first byte of every packet a client sends
+const JOIN: u8 = b'J';
+const LEAVE: u8 = b'L';
+const PING: u8 = b'P';
+const CHAT: u8 = b'C';
+//these two also start what the server sends
+const HELLO: u8 = b'H';
+const SEALED: u8 = b'S';
+const FULL: u8 = b'F';
+const NO_SESSION: u8 = b'N';
+
+//kind byte and client id in front of every packet
+const HEADER_LEN: usize = 1 + 8;
+
+// who a packet is from. the server keys its clients by it rather than by source address, which can
+// change under a client or be shared by several. it is taken from the client's handshake key, so
+// nobody else can open a session under the same id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClientId(pub u64);
+
+impl ClientId {
+    pub fn of(public_key: &[u8; 32]) -> ClientId {
+        let hash = Sha256::digest(public_key);
+        ClientId(u64::from_be_bytes(hash[..8].try_into().unwrap()))
+    }
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+// what a client tells the server. every packet travels as the payload of one reliable datagram,
+// a kind byte and the sender's id followed by utf-8 text where there is any.
+// apart from the hello everything goes inside a sealed packet once the handshake is done
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    //the client's half of the key exchange
+    Hello { key: [u8; 32] },
+    //another packet, encrypted and authenticated under the session keys
+    Sealed { data: Vec<u8> },
+    Join { name: String },
+    Leave,
+    //a heartbeat, sent while the user is quiet so the server knows the client is still there
+    Ping,
+    Chat { text: String },
+}
+
+// what the server tells a client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerPacket {
+    //the server's half of the key exchange
+    Hello { key: [u8; 32] },
+    //a chat line, encrypted for this client only
+    Sealed { data: Vec<u8> },
+    //no room for another client
+    Full,
+    //a sealed packet from `id` came in, but there is no session for it, e.g. it was dropped for being silent
+    NoSession { id: ClientId },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidPacket;
+
+impl fmt::Display for InvalidPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid packet")
+    }
+}
+
+impl std::error::Error for InvalidPacket {}
+
+impl Packet {
+    pub fn encode(&self, from: ClientId) -> Vec<u8> {
+        let (kind, body) = match self {
+            Packet::Hello { key } => (HELLO, key.as_slice()),
+            Packet::Sealed { data } => (SEALED, data.as_slice()),
+            Packet::Join { name } => (JOIN, name.as_bytes()),
+            Packet::Leave => (LEAVE, &[][..]),
+            Packet::Ping => (PING, &[][..]),
+            Packet::Chat { text } => (CHAT, text.as_bytes()),
+        };
+        let mut encoded = Vec::with_capacity(HEADER_LEN + body.len());
+        encoded.push(kind);
+        encoded.extend_from_slice(&from.0.to_be_bytes());
+        encoded.extend_from_slice(body);
+        encoded
+    }
+
+    //this packet sealed for the server, ready to send
+    pub fn seal(&self, from: ClientId, sealer: &mut Sealer) -> Vec<u8> {
+        let data = sealer.seal(&from.0.to_be_bytes(), &self.encode(from));
+        Packet::Sealed { data }.encode(from)
+    }
+
+    //the packet inside a sealed one from `from`, which has to name the same client
+    pub fn open(from: ClientId, data: &[u8], opener: &mut Opener) -> Result<Packet, SecureError> {
+        let plaintext = opener.open(&from.0.to_be_bytes(), data)?;
+        match Packet::decode(&plaintext) {
+            Ok((inner, packet)) if inner == from && !matches!(packet, Packet::Hello { .. } | Packet::Sealed { .. }) => Ok(packet),
+            _ => Err(SecureError::Invalid),
+        }
+    }
+
+    pub fn decode(payload: &[u8]) -> Result<(ClientId, Packet), InvalidPacket> {
+        if payload.len() < HEADER_LEN {
+            return Err(InvalidPacket);
+        }
+        let from = ClientId(u64::from_be_bytes(payload[1..HEADER_LEN].try_into().map_err(|_| InvalidPacket)?));
+        let rest = &payload[HEADER_LEN..];
+        let text = || std::str::from_utf8(rest).map(str::to_string).map_err(|_| InvalidPacket);
+        let packet = match payload[0] {
+            HELLO => Packet::Hello { key: rest.try_into().map_err(|_| InvalidPacket)? },
+            SEALED => Packet::Sealed { data: rest.to_vec() },
+            JOIN => Packet::Join { name: text()? },
+            LEAVE if rest.is_empty() => Packet::Leave,
+            PING if rest.is_empty() => Packet::Ping,
+            CHAT => Packet::Chat { text: text()? },
+            _ => return Err(InvalidPacket),
+        };
+        Ok((from, packet))
+    }
+}
+
+impl ServerPacket {
+    pub fn encode(&self) -> Vec<u8> {
+        let id;
+        let (kind, body) = match self {
+            ServerPacket::Hello { key } => (HELLO, key.as_slice()),
+            ServerPacket::Sealed { data } => (SEALED, data.as_slice()),
+            ServerPacket::Full => (FULL, &[][..]),
+            ServerPacket::NoSession { id: client } => {
+                id = client.0.to_be_bytes();
+                (NO_SESSION, id.as_slice())
+            }
+        };
+        let mut encoded = Vec::with_capacity(1 + body.len());
+        encoded.push(kind);
+        encoded.extend_from_slice(body);
+        encoded
+    }
+
+    pub fn decode(payload: &[u8]) -> Result<ServerPacket, InvalidPacket> {
+        let (&kind, rest) = payload.split_first().ok_or(InvalidPacket)?;
+        match kind {
+            HELLO => Ok(ServerPacket::Hello { key: rest.try_into().map_err(|_| InvalidPacket)? }),
+            SEALED => Ok(ServerPacket::Sealed { data: rest.to_vec() }),
+            FULL if rest.is_empty() => Ok(ServerPacket::Full),
+            NO_SESSION => Ok(ServerPacket::NoSession { id: ClientId(u64::from_be_bytes(rest.try_into().map_err(|_| InvalidPacket)?)) }),
+            _ => Err(InvalidPacket),
+        }
+    }
+
+    //a chat line sealed for one client, ready to send
+    pub fn seal(text: &str, sealer: &mut Sealer) -> Vec<u8> {
+        ServerPacket::Sealed { data: sealer.seal(&[SEALED], text.as_bytes()) }.encode()
+    }
+
+    pub fn open(data: &[u8], opener: &mut Opener) -> Result<String, SecureError> {
+        let plaintext = opener.open(&[SEALED], data)?;
+        String::from_utf8(plaintext).map_err(|_| SecureError::Invalid)
+    }
+}
@@ -0,0 +1,389 @@
+use crate::fragment::{self, Reassembly};
+use crate::{Transport, MAX_DATAGRAM, MAX_MESSAGE};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::time::Instant;
+
+//first byte of every datagram
+const DATA: u8 = 0;
+const ACK: u8 = 1;
+
+//kind, epoch, seq and base in front of every payload. the base is the oldest seq the sender still
+//waits for an ack on, everything before it was acked at some point
+const HEADER_LEN: usize = 1 + 4 + 8 + 8;
+
+//epochs of a peer we receive in side by side. nothing vouches for the epoch in a datagram, so a new one
+//doesn't replace the one the peer has been using, it only takes over once that one has gone quiet
+const EPOCHS: usize = 4;
+
+//the smallest mtu that still leaves room for some payload next to the headers
+pub const MIN_MTU: usize = 64;
+
+//how the endpoint deals with loss
+#[derive(Debug, Clone, Copy)]
+pub struct Reliability {
+    //how long a datagram waits for its ack before it is sent again, doubling with every retry
+    pub retransmit_after: Duration,
+    //retries before the peer is given up on
+    pub max_retries: u32,
+    //how many datagrams may be on their way to a peer unacked, the receiver drops anything further ahead
+    pub window: u64,
+    //largest datagram put on the wire, payloads that don't fit are split into fragments
+    pub mtu: usize,
+    //how long the fragments of one payload may take to all arrive
+    pub reassembly_timeout: Duration,
+    //how long a peer that has nothing pending is kept after the last datagram to or from it,
+    //it has to be well over the time between heartbeats
+    pub idle_timeout: Duration,
+}
+
+impl Default for Reliability {
+    fn default() -> Reliability {
+        Reliability {
+            retransmit_after: Duration::from_millis(200),
+            max_retries: 8,
+            window: 256,
+            //fits in an ethernet frame with room to spare for tunnels
+            mtu: 1200,
+            reassembly_timeout: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(120),
+        }
+    }
+}
+
+//a fragment waiting for its ack
+struct Pending {
+    fragment: Vec<u8>,
+    //not sent at all yet, it was outside the window
+    sent: bool,
+    due: Instant,
+    retries: u32,
+}
+
+//what arrived from a peer in one of its epochs, a new epoch means it restarted and counts from 1 again
+struct Inbound {
+    epoch: u32,
+    expected: u64,
+    //datagrams that arrived ahead of a gap
+    early: BTreeMap<u64, Vec<u8>>,
+    reassembly: Reassembly,
+    heard: Instant,
+}
+
+impl Inbound {
+    fn new(epoch: u32) -> Inbound {
+        Inbound { epoch, expected: 1, early: BTreeMap::new(), reassembly: Reassembly::default(), heard: Instant::now() }
+    }
+}
+
+//what we know about one peer, in both directions
+struct Peer {
+    //ours towards this peer, picked at random and again whenever we give up on it,
+    //so the peer notices that counting starts over
+    out_epoch: u32,
+    next_seq: u64,
+    pending: BTreeMap<u64, Pending>,
+    //the epoch the peer has been using first, newer ones it may have moved on to behind it
+    inbound: Vec<Inbound>,
+    //when anything was last sent to or received from the peer
+    last_active: Instant,
+}
+
+impl Peer {
+    fn new() -> Peer {
+        Peer { out_epoch: rand::random(), next_seq: 0, pending: BTreeMap::new(), inbound: Vec::new(), last_active: Instant::now() }
+    }
+
+    //the receiving side of `epoch`, started if it is new
+    fn inbound(&mut self, epoch: u32, quiet: Duration) -> &mut Inbound {
+        let now = Instant::now();
+        //an epoch silent for as long as an idle peer is kept is over, the newer one heard from last takes its place
+        if self.inbound.len() > 1 && now.duration_since(self.inbound[0].heard) > quiet {
+            self.inbound.remove(0);
+            let latest = (0..self.inbound.len()).max_by_key(|&i| self.inbound[i].heard).unwrap();
+            self.inbound.swap(0, latest);
+        }
+        let index = match self.inbound.iter().position(|inbound| inbound.epoch == epoch) {
+            Some(index) => index,
+            None => {
+                //making room among the newer ones, whatever they send the one in use stays
+                if self.inbound.len() == EPOCHS {
+                    let stalest = (1..EPOCHS).min_by_key(|&i| self.inbound[i].heard).unwrap();
+                    self.inbound.remove(stalest);
+                }
+                self.inbound.push(Inbound::new(epoch));
+                self.inbound.len() - 1
+            }
+        };
+        let inbound = &mut self.inbound[index];
+        inbound.heard = now;
+        inbound
+    }
+}
+
+// reliable, ordered delivery of payloads between this socket and any number of peers.
+// payloads are cut into fragments that fit the mtu, every fragment carries a sequence number per peer
+// and is sent again until the peer acks it; the receiving side drops duplicates, holds back anything
+// that arrives ahead of a gap and puts the fragments back together
+pub struct Endpoint<T> {
+    transport: Arc<T>,
+    peers: Arc<Mutex<HashMap<SocketAddr, Peer>>>,
+    config: Reliability,
+    admit: Admission,
+}
+
+// deciding whether a data datagram from an address is looked at at all. one that isn't creates
+// no state and isn't acked, so the sender sends it again later. acks are always looked at, they
+// only ever touch peers that already exist
+pub type Admission = Arc<dyn Fn(SocketAddr) -> bool + Send + Sync>;
+
+//not derived, that would require the transport itself to be Clone
+impl<T> Clone for Endpoint<T> {
+    fn clone(&self) -> Endpoint<T> {
+        Endpoint { transport: self.transport.clone(), peers: self.peers.clone(), config: self.config, admit: self.admit.clone() }
+    }
+}
+
+impl<T: Transport> Endpoint<T> {
+    // starting to receive on `transport`. payloads come out of the returned channel in the order
+    // each peer sent them, exactly once; the endpoint stops once the channel is dropped
+    pub fn start(transport: T, config: Reliability) -> (Endpoint<T>, UnboundedReceiver<(SocketAddr, Vec<u8>)>) {
+        Endpoint::start_guarded(transport, config, Arc::new(|_| true))
+    }
+
+    //the same, but only data from addresses `admit` lets through is taken in
+    pub fn start_guarded(transport: T, config: Reliability, admit: Admission) -> (Endpoint<T>, UnboundedReceiver<(SocketAddr, Vec<u8>)>) {
+        assert!((MIN_MTU..=MAX_DATAGRAM).contains(&config.mtu), "mtu out of range");
+        let endpoint = Endpoint { transport: Arc::new(transport), peers: Arc::new(Mutex::new(HashMap::new())), config, admit };
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(endpoint.clone().drive(tx));
+        (endpoint, rx)
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    //sending a payload to `to`, it is retransmitted in the background until acked
+    pub async fn send(&self, to: SocketAddr, payload: &[u8]) -> io::Result<()> {
+        if payload.len() > MAX_MESSAGE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "payload too large"));
+        }
+        let fragments = fragment::split(payload, self.config.mtu - HEADER_LEN - fragment::HEADER_LEN);
+
+        let now = Instant::now();
+        let mut datagrams = Vec::new();
+        {
+            let mut peers = self.peers.lock().unwrap();
+            let peer = peers.entry(to).or_insert_with(Peer::new);
+            peer.last_active = now;
+            for fragment in fragments {
+                peer.next_seq += 1;
+                //whatever is past the window waits for the retransmit tick to send it for the first time
+                let base = peer.pending.keys().next().copied().unwrap_or(peer.next_seq);
+                let sent = peer.next_seq < base + self.config.window;
+                if sent {
+                    datagrams.push(encode(DATA, peer.out_epoch, peer.next_seq, base, &fragment));
+                }
+                let due = if sent { now + self.config.retransmit_after } else { now };
+                peer.pending.insert(peer.next_seq, Pending { fragment, sent, due, retries: 0 });
+            }
+        }
+
+        for datagram in datagrams {
+            self.transport.send_to(&datagram, to).await?;
+        }
+        Ok(())
+    }
+
+    //dropping everything about `to`, whatever is still pending for it included
+    pub fn forget(&self, to: SocketAddr) {
+        self.peers.lock().unwrap().remove(&to);
+    }
+
+    //how many peers the endpoint keeps state for
+    pub fn peers(&self) -> usize {
+        self.peers.lock().unwrap().len()
+    }
+
+    //waiting until everything sent to `to` was acked, or given up on
+    pub async fn flush(&self, to: SocketAddr) {
+        loop {
+            let pending = self.peers.lock().unwrap().get(&to).map_or(0, |peer| peer.pending.len());
+            if pending == 0 {
+                return;
+            }
+            tokio::time::sleep(self.config.retransmit_after / 4).await;
+        }
+    }
+
+    //receiving datagrams and retransmitting what wasn't acked in time
+    async fn drive(self, deliveries: UnboundedSender<(SocketAddr, Vec<u8>)>) {
+        let mut buf = vec![0; MAX_DATAGRAM];
+        let mut tick = tokio::time::interval(self.config.retransmit_after / 2);
+        tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                received = self.transport.recv_from(&mut buf) => {
+                    let (len, from) = match received {
+                        Ok(received) => received,
+                        //e.g. an icmp port unreachable from a peer that went away, the socket itself is fine
+                        Err(e) => {
+                            log::debug!("Error receiving datagram: {}", e);
+                            continue;
+                        }
+                    };
+                    for payload in self.receive(&buf[..len], from).await {
+                        if deliveries.send((from, payload)).is_err() {
+                            return;
+                        }
+                    }
+                }
+                _ = tick.tick() => {
+                    if deliveries.is_closed() {
+                        return;
+                    }
+                    self.retransmit().await;
+                }
+            }
+        }
+    }
+
+    //handling one incoming datagram, returning the payloads it completes
+    async fn receive(&self, datagram: &[u8], from: SocketAddr) -> Vec<Vec<u8>> {
+        let Some((kind, epoch, seq, base, payload)) = decode(datagram) else {
+            log::debug!("Ignoring malformed datagram from {}", from);
+            return Vec::new();
+        };
+
+        if kind == ACK {
+            //acks from before a restart are for datagrams we no longer have
+            if let Some(peer) = self.peers.lock().unwrap().get_mut(&from) {
+                if epoch == peer.out_epoch {
+                    peer.pending.remove(&seq);
+                    peer.last_active = Instant::now();
+                }
+            }
+            return Vec::new();
+        }
+        if !(self.admit)(from) {
+            return Vec::new();
+        }
+
+        let (ack, delivered) = {
+            let mut peers = self.peers.lock().unwrap();
+            let peer = peers.entry(from).or_insert_with(Peer::new);
+            peer.last_active = Instant::now();
+            let inbound = peer.inbound(epoch, self.config.idle_timeout);
+            //the sender had everything before its base acked, by us before we forgot about it.
+            //we only ever ack what we hold on to, so this can't skip anything we still need
+            if base > inbound.expected {
+                inbound.early.retain(|held, _| *held >= base);
+                inbound.expected = base;
+                inbound.reassembly.clear();
+            }
+
+            if seq >= inbound.expected + self.config.window {
+                //too far ahead to hold on to, the sender will try again
+                (false, Vec::new())
+            } else if seq < inbound.expected || inbound.early.contains_key(&seq) {
+                //a duplicate, most likely because our ack got lost
+                (true, Vec::new())
+            } else {
+                inbound.early.insert(seq, payload.to_vec());
+                let mut delivered = Vec::new();
+                while let Some(fragment) = inbound.early.remove(&inbound.expected) {
+                    inbound.expected += 1;
+                    if let Some(payload) = inbound.reassembly.push(&fragment, MAX_MESSAGE) {
+                        delivered.push(payload);
+                    }
+                }
+                (true, delivered)
+            }
+        };
+
+        if ack {
+            if let Err(e) = self.transport.send_to(&encode(ACK, epoch, seq, 0, &[]), from).await {
+                log::debug!("Failed to ack {}: {}", from, e);
+            }
+        }
+        delivered
+    }
+
+    // sending again whatever is overdue, and for the first time whatever the window has made room for.
+    // peers that stopped answering are given up on, and so are their fragments that never completed.
+    // peers nothing was heard from or sent to for a while are forgotten altogether
+    async fn retransmit(&self) {
+        let now = Instant::now();
+        let mut resend = Vec::new();
+        {
+            let mut peers = self.peers.lock().unwrap();
+            peers.retain(|addr, peer| {
+                let idle = peer.pending.is_empty() && now.duration_since(peer.last_active) > self.config.idle_timeout;
+                if idle {
+                    log::debug!("Forgetting idle peer {}", addr);
+                }
+                !idle
+            });
+            for (addr, peer) in peers.iter_mut() {
+                for inbound in &mut peer.inbound {
+                    inbound.reassembly.expire(self.config.reassembly_timeout);
+                }
+
+                let overdue = |pending: &Pending| pending.sent && pending.retries >= self.config.max_retries && pending.due <= now;
+                if peer.pending.values().any(overdue) {
+                    log::warn!("Giving up on {} after {} retries, dropping {} datagrams", addr, self.config.max_retries, peer.pending.len());
+                    peer.pending.clear();
+                    //starting over, the peer would otherwise wait for the lost datagrams forever
+                    peer.out_epoch = rand::random();
+                    peer.next_seq = 0;
+                    continue;
+                }
+
+                let Some(&base) = peer.pending.keys().next() else { continue };
+                for (&seq, pending) in peer.pending.range_mut(..base + self.config.window) {
+                    if pending.due > now {
+                        continue;
+                    }
+                    if pending.sent {
+                        pending.retries += 1;
+                    }
+                    pending.sent = true;
+                    pending.due = now + self.config.retransmit_after * 2u32.pow(pending.retries.min(16));
+                    resend.push((*addr, encode(DATA, peer.out_epoch, seq, base, &pending.fragment)));
+                }
+            }
+        }
+
+        for (addr, datagram) in resend {
+            if let Err(e) = self.transport.send_to(&datagram, addr).await {
+                log::debug!("Failed to retransmit to {}: {}", addr, e);
+            }
+        }
+    }
+}
+
+fn encode(kind: u8, epoch: u32, seq: u64, base: u64, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(HEADER_LEN + payload.len());
+    datagram.push(kind);
+    datagram.extend_from_slice(&epoch.to_be_bytes());
+    datagram.extend_from_slice(&seq.to_be_bytes());
+    datagram.extend_from_slice(&base.to_be_bytes());
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+fn decode(datagram: &[u8]) -> Option<(u8, u32, u64, u64, &[u8])> {
+    if datagram.len() < HEADER_LEN || (datagram[0] != DATA && datagram[0] != ACK) {
+        return None;
+    }
+    let epoch = u32::from_be_bytes(datagram[1..5].try_into().ok()?);
+    let seq = u64::from_be_bytes(datagram[5..13].try_into().ok()?);
+    let base = u64::from_be_bytes(datagram[13..HEADER_LEN].try_into().ok()?);
+    Some((datagram[0], epoch, seq, base, &datagram[HEADER_LEN..]))
+}
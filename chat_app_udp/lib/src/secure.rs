@@ -0,0 +1,125 @@
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+//the counter in front of every sealed payload
+const COUNTER_LEN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecureError {
+    //too short, tampered with or sealed under another key
+    Invalid,
+    //a counter we already saw, or an older one
+    Replayed,
+}
+
+impl fmt::Display for SecureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecureError::Invalid => f.write_str("payload failed to authenticate"),
+            SecureError::Replayed => f.write_str("payload was replayed"),
+        }
+    }
+}
+
+impl std::error::Error for SecureError {}
+
+// our half of an x25519 key exchange. the public key goes to the other side in a hello,
+// theirs comes back and both ends derive the same pair of session keys
+pub struct Handshake {
+    secret: EphemeralSecret,
+    public: PublicKey,
+}
+
+impl Handshake {
+    pub fn new() -> Handshake {
+        let secret = EphemeralSecret::random_from_rng(rand::rngs::OsRng);
+        let public = PublicKey::from(&secret);
+        Handshake { secret, public }
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public.to_bytes()
+    }
+
+    //finishing with the other side's key. `initiator` is the client, which picks the keys apart the other way round
+    pub fn finish(self, peer: [u8; 32], initiator: bool) -> (Sealer, Opener) {
+        let ours = self.public.to_bytes();
+        let shared = self.secret.diffie_hellman(&PublicKey::from(peer));
+        let (client, server) = if initiator { (ours, peer) } else { (peer, ours) };
+        let key = |label: &[u8]| {
+            let mut hasher = Sha256::new();
+            hasher.update(label);
+            hasher.update(shared.as_bytes());
+            hasher.update(client);
+            hasher.update(server);
+            ChaCha20Poly1305::new(&hasher.finalize())
+        };
+        let (upstream, downstream) = (key(b"chat_app_udp client to server"), key(b"chat_app_udp server to client"));
+        let (send, receive) = if initiator { (upstream, downstream) } else { (downstream, upstream) };
+        (Sealer { cipher: send, counter: 0 }, Opener { cipher: receive, last: 0 })
+    }
+}
+
+impl Default for Handshake {
+    fn default() -> Handshake {
+        Handshake::new()
+    }
+}
+
+// sealing payloads in one direction of a session. every payload takes the next counter as its nonce,
+// so no nonce is ever used twice under the same key
+pub struct Sealer {
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl Sealer {
+    //the counter followed by the ciphertext, `aad` is authenticated along with it but not included
+    pub fn seal(&mut self, aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        self.counter += 1;
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce(self.counter), Payload { msg: plaintext, aad })
+            .expect("payload too large to seal");
+        let mut sealed = Vec::with_capacity(COUNTER_LEN + ciphertext.len());
+        sealed.extend_from_slice(&self.counter.to_be_bytes());
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+}
+
+// opening what the other side sealed. payloads arrive in order over the reliable endpoint,
+// so anything that doesn't carry a higher counter than the last one is a replay
+pub struct Opener {
+    cipher: ChaCha20Poly1305,
+    last: u64,
+}
+
+impl Opener {
+    pub fn open(&mut self, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, SecureError> {
+        if sealed.len() < COUNTER_LEN {
+            return Err(SecureError::Invalid);
+        }
+        let (counter, ciphertext) = sealed.split_at(COUNTER_LEN);
+        let counter = u64::from_be_bytes(counter.try_into().map_err(|_| SecureError::Invalid)?);
+        if counter <= self.last {
+            return Err(SecureError::Replayed);
+        }
+        let plaintext = self
+            .cipher
+            .decrypt(&nonce(counter), Payload { msg: ciphertext, aad })
+            .map_err(|_| SecureError::Invalid)?;
+        self.last = counter;
+        Ok(plaintext)
+    }
+}
+
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
@@ -0,0 +1,51 @@
+use lib::discovery::{announce, discover, multicast_socket, Announcement, Role};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+
+#[tokio::test]
+async fn listeners_on_a_group_hear_each_other() {
+    let group = SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, 1), 47_101);
+    let first = multicast_socket(group, Ipv4Addr::LOCALHOST).unwrap();
+    let second = multicast_socket(group, Ipv4Addr::LOCALHOST).unwrap();
+
+    first.send_to(b"hello group", group).await.unwrap();
+    for socket in [&first, &second] {
+        let mut buf = [0; 64];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(5), socket.recv_from(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"hello group");
+    }
+}
+
+#[test]
+fn unicast_groups_are_refused() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    assert!(multicast_socket(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 47_102), Ipv4Addr::LOCALHOST).is_err());
+}
+
+#[test]
+fn announcements_survive_a_round_trip() {
+    for role in [Role::Peer, Role::Server] {
+        let announcement = Announcement { role, id: 42, port: 8080, name: "lobby".to_string() };
+        assert_eq!(Announcement::decode(&announcement.encode()), Some(announcement));
+    }
+    assert_eq!(Announcement::decode(b"hello group"), None);
+    assert_eq!(Announcement::decode(b"AP123"), None);
+}
+
+#[tokio::test]
+async fn servers_are_discovered() {
+    let group = SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, 2), 47_103);
+    for (role, id, name) in [(Role::Server, 1, "lobby"), (Role::Server, 2, "games"), (Role::Peer, 3, "alice")] {
+        let socket = multicast_socket(group, Ipv4Addr::LOCALHOST).unwrap();
+        let announcement = Announcement { role, id, port: 8000 + id as u16, name: name.to_string() };
+        tokio::spawn(async move { announce(&socket, group, &announcement, Duration::from_millis(100)).await });
+    }
+
+    //peers on the same group aren't servers, and every server is listed once however often it announces
+    let listener = multicast_socket(group, Ipv4Addr::LOCALHOST).unwrap();
+    let mut found = discover(&listener, Duration::from_secs(1)).await.unwrap();
+    found.sort_by_key(|(_, announcement)| announcement.id);
+    let found: Vec<(SocketAddr, String)> = found.into_iter().map(|(addr, announcement)| (addr, announcement.name)).collect();
+    assert_eq!(found, [(SocketAddr::from(([127, 0, 0, 1], 8001)), "lobby".to_string()), (SocketAddr::from(([127, 0, 0, 1], 8002)), "games".to_string())]);
+}
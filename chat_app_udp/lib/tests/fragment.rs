@@ -0,0 +1,57 @@
+use lib::fragment::{split, Reassembly};
+use std::time::Duration;
+
+const MAX_LEN: usize = 1024;
+
+#[test]
+fn fragments_are_put_back_together() {
+    let payload: Vec<u8> = (0..=255).collect();
+    let fragments = split(&payload, 100);
+    assert_eq!(fragments.len(), 3);
+
+    let mut reassembly = Reassembly::default();
+    assert_eq!(reassembly.push(&fragments[0], MAX_LEN), None);
+    assert_eq!(reassembly.push(&fragments[1], MAX_LEN), None);
+    assert_eq!(reassembly.push(&fragments[2], MAX_LEN), Some(payload));
+
+    //an empty payload is a single fragment
+    let empty = split(&[], 100);
+    assert_eq!(empty.len(), 1);
+    assert_eq!(reassembly.push(&empty[0], MAX_LEN), Some(Vec::new()));
+}
+
+#[test]
+fn incomplete_sets_are_discarded() {
+    let first = split(&[1; 30], 10);
+    let second = split(&[2; 30], 10);
+    let mut reassembly = Reassembly::default();
+
+    //a new set starting before the old one is complete
+    reassembly.push(&first[0], MAX_LEN);
+    reassembly.push(&first[1], MAX_LEN);
+    assert_eq!(reassembly.push(&second[0], MAX_LEN), None);
+    assert_eq!(reassembly.push(&first[2], MAX_LEN), None);
+
+    //a gap throws the set away, and what follows it without a start is ignored
+    reassembly.push(&second[0], MAX_LEN);
+    assert_eq!(reassembly.push(&second[2], MAX_LEN), None);
+    assert_eq!(reassembly.push(&second[1], MAX_LEN), None);
+
+    //sets over the limit aren't collected
+    let big = split(&[3; 30], 10);
+    reassembly.push(&big[0], 25);
+    reassembly.push(&big[1], 25);
+    assert_eq!(reassembly.push(&big[2], 25), None);
+}
+
+#[test]
+fn stale_sets_expire() {
+    let fragments = split(&[7; 20], 10);
+    let mut reassembly = Reassembly::default();
+    reassembly.push(&fragments[0], MAX_LEN);
+
+    reassembly.expire(Duration::from_secs(60));
+    std::thread::sleep(Duration::from_millis(5));
+    reassembly.expire(Duration::from_millis(1));
+    assert_eq!(reassembly.push(&fragments[1], MAX_LEN), None);
+}
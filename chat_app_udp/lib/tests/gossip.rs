@@ -0,0 +1,80 @@
+use lib::gossip::{Gossip, Member, Membership, MessageId, PeerId, Seen};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+fn member(id: u64, name: &str, port: u16, heartbeat: u64) -> Member {
+    Member { id: PeerId(id), name: name.to_string(), addr: SocketAddr::from(([127, 0, 0, 1], port)), heartbeat }
+}
+
+fn addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+#[test]
+fn gossip_survives_a_round_trip() {
+    let messages = [
+        Gossip::Members { members: vec![member(1, "alice", 4000, 7), member(2, "bøb", 4001, 0)] },
+        Gossip::Members { members: Vec::new() },
+        Gossip::Chat { id: MessageId { origin: PeerId(3), seq: 9 }, name: "carl".to_string(), text: "hi there".to_string() },
+        Gossip::Leave { id: PeerId(4) },
+    ];
+    for message in messages {
+        assert_eq!(Gossip::decode(&message.encode()), Ok(message));
+    }
+    assert!(Gossip::decode(b"").is_err());
+    assert!(Gossip::decode(b"X123").is_err());
+    let mut trailing = Gossip::Leave { id: PeerId(4) }.encode();
+    trailing.push(0);
+    assert!(Gossip::decode(&trailing).is_err());
+}
+
+#[test]
+fn views_are_merged() {
+    let mut membership = Membership::new(PeerId(1));
+
+    //the sender is reachable where its datagram came from, and we never list ourselves
+    let joined = membership.merge(addr(5002), vec![member(2, "bob", 9999, 1), member(3, "carl", 5003, 1), member(1, "me", 5001, 1)]);
+    assert_eq!(joined.len(), 2);
+    assert_eq!(membership.len(), 2);
+    assert_eq!(membership.get(PeerId(2)).unwrap().addr, addr(5002));
+    assert!(membership.get(PeerId(1)).is_none());
+
+    //an older heartbeat changes nothing, a newer one is taken
+    assert!(membership.merge(addr(5002), vec![member(2, "bob", 0, 1), member(3, "carl", 5003, 0)]).is_empty());
+    membership.merge(addr(5002), vec![member(2, "bob", 0, 2), member(3, "carl", 5003, 5)]);
+    assert_eq!(membership.get(PeerId(3)).unwrap().heartbeat, 5);
+}
+
+#[test]
+fn departed_peers_stay_gone() {
+    let mut membership = Membership::new(PeerId(1));
+    membership.merge(addr(5002), vec![member(2, "bob", 0, 3), member(3, "carl", 5003, 3)]);
+
+    //a goodbye sticks even when someone still gossips about the peer
+    assert_eq!(membership.leave(PeerId(3)).unwrap().name, "carl");
+    assert!(membership.merge(addr(5002), vec![member(2, "bob", 0, 4), member(3, "carl", 5003, 100)]).is_empty());
+    assert!(membership.get(PeerId(3)).is_none());
+
+    //a peer that went quiet only comes back once its heartbeat moves on
+    std::thread::sleep(Duration::from_millis(20));
+    let expired = membership.expire(Duration::from_millis(10));
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].name, "bob");
+    assert!(membership.is_empty());
+    assert_eq!(membership.merge(addr(5004), vec![member(4, "dave", 0, 1), member(2, "bob", 5002, 4)]).len(), 1);
+    assert!(membership.get(PeerId(2)).is_none());
+    assert_eq!(membership.merge(addr(5002), vec![member(2, "bob", 0, 5)]).len(), 1);
+}
+
+#[test]
+fn messages_are_seen_once() {
+    let mut seen = Seen::new(2);
+    let id = |seq| MessageId { origin: PeerId(1), seq };
+    assert!(seen.insert(id(1)));
+    assert!(!seen.insert(id(1)));
+    assert!(seen.insert(id(2)));
+    assert!(seen.insert(MessageId { origin: PeerId(2), seq: 1 }));
+    //the oldest one has been forgotten by now
+    assert!(seen.insert(id(1)));
+    assert!(!seen.insert(id(1)));
+}
@@ -0,0 +1,77 @@
+use lib::limit::{Dropped, Limiter, Limits, Rate, TokenBucket, Verdict};
+use lib::packet::ClientId;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+fn limits(ban_after: u32) -> Limits {
+    Limits {
+        per_address: Rate { per_second: 10.0, burst: 3.0 },
+        per_client: Rate { per_second: 1.0, burst: 2.0 },
+        global: Rate { per_second: 1.0, burst: 5.0 },
+        ban_after,
+        ban_for: Duration::from_millis(300),
+    }
+}
+
+fn ip(last: u8) -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+}
+
+#[test]
+fn buckets_allow_a_burst_and_then_refill() {
+    let mut bucket = TokenBucket::new(Rate { per_second: 20.0, burst: 3.0 });
+    assert!((0..3).all(|_| bucket.take()));
+    assert!(!bucket.take());
+    std::thread::sleep(Duration::from_millis(120));
+    assert!(bucket.take());
+    assert!(bucket.take());
+    assert!(!bucket.take());
+}
+
+#[test]
+fn senders_are_limited_separately_within_a_global_budget() {
+    let mut limiter = Limiter::new(limits(0));
+
+    //one address going over its limit leaves the budget for the others
+    let first: Vec<Verdict> = (0..5).map(|_| limiter.check_address(ip(1))).collect();
+    assert_eq!(first, [Verdict::Pass, Verdict::Pass, Verdict::Pass, Verdict::Drop, Verdict::Drop]);
+    assert_eq!(limiter.check_address(ip(2)), Verdict::Pass);
+    assert_eq!(limiter.check_address(ip(2)), Verdict::Pass);
+    //but all of them together run out of it
+    assert_eq!(limiter.check_address(ip(3)), Verdict::Drop);
+
+    //clients are limited no matter which address they come from
+    assert_eq!(limiter.check_client(ClientId(7)), Verdict::Pass);
+    assert_eq!(limiter.check_client(ClientId(7)), Verdict::Pass);
+    assert_eq!(limiter.check_client(ClientId(7)), Verdict::Drop);
+    assert_eq!(limiter.check_client(ClientId(8)), Verdict::Pass);
+
+    assert_eq!(limiter.dropped(), Dropped { address: 2, client: 1, global: 1, banned: 0 });
+    assert_eq!(limiter.dropped().total(), 4);
+}
+
+#[test]
+fn repeat_offenders_are_banned_for_a_while() {
+    let mut limiter = Limiter::new(limits(3));
+    let verdicts: Vec<Verdict> = (0..7).map(|_| limiter.check_address(ip(1))).collect();
+    assert_eq!(verdicts, [Verdict::Pass, Verdict::Pass, Verdict::Pass, Verdict::Drop, Verdict::Drop, Verdict::Ban, Verdict::Drop]);
+    assert_eq!(limiter.dropped(), Dropped { address: 3, client: 0, global: 0, banned: 1 });
+
+    //a refilled bucket doesn't help while the ban lasts
+    std::thread::sleep(Duration::from_millis(150));
+    assert_eq!(limiter.check_address(ip(1)), Verdict::Drop);
+    std::thread::sleep(Duration::from_millis(200));
+    assert_eq!(limiter.check_address(ip(1)), Verdict::Pass);
+}
+
+#[test]
+fn quiet_senders_are_forgotten() {
+    let mut limiter = Limiter::new(limits(0));
+    limiter.check_address(ip(1));
+    limiter.check_client(ClientId(1));
+    assert_eq!(limiter.len(), 2);
+    //once their buckets are full again
+    std::thread::sleep(Duration::from_millis(1100));
+    limiter.prune();
+    assert!(limiter.is_empty());
+}
@@ -0,0 +1,71 @@
+use lib::packet::{ClientId, Packet, ServerPacket};
+use lib::secure::{Handshake, SecureError};
+
+#[test]
+fn packets_survive_a_round_trip() {
+    let packets = [
+        Packet::Hello { key: [7; 32] },
+        Packet::Sealed { data: vec![0, 1, 2, 255] },
+        Packet::Join { name: "alice".to_string() },
+        Packet::Leave,
+        Packet::Ping,
+        Packet::Chat { text: "héllo".to_string() },
+        Packet::Chat { text: String::new() },
+    ];
+    let id = ClientId::of(&[1; 32]);
+    for packet in packets {
+        assert_eq!(Packet::decode(&packet.encode(id)), Ok((id, packet)));
+    }
+    for packet in [ServerPacket::Hello { key: [9; 32] }, ServerPacket::Sealed { data: vec![3; 40] }, ServerPacket::Full, ServerPacket::NoSession { id }] {
+        assert_eq!(ServerPacket::decode(&packet.encode()), Ok(packet));
+    }
+}
+
+#[test]
+fn garbage_is_rejected() {
+    assert!(Packet::decode(b"").is_err());
+    assert!(Packet::decode(b"alice : hello").is_err());
+    //too short to hold the client id
+    assert!(Packet::decode(b"P1234").is_err());
+    assert!(Packet::decode(b"P12345678extra").is_err());
+    assert!(Packet::decode(b"C12345678\xff\xfe").is_err());
+    //a key of the wrong length
+    assert!(Packet::decode(b"H12345678short").is_err());
+    assert!(ServerPacket::decode(b"Fx").is_err());
+    assert!(ServerPacket::decode(b"N1234").is_err());
+}
+
+#[test]
+fn sealed_packets_reach_only_the_other_end() {
+    let (client, server) = (Handshake::new(), Handshake::new());
+    let (client_key, server_key) = (client.public_key(), server.public_key());
+    let id = ClientId::of(&client_key);
+    let (mut client_sealer, mut client_opener) = client.finish(server_key, true);
+    let (mut server_sealer, mut server_opener) = server.finish(client_key, false);
+
+    let sealed = Packet::Chat { text: "hello".to_string() }.seal(id, &mut client_sealer);
+    let Ok((from, Packet::Sealed { data })) = Packet::decode(&sealed) else { panic!("not a sealed packet") };
+    assert_eq!(from, id);
+    assert!(!data.windows(5).any(|window| window == b"hello"));
+    assert_eq!(Packet::open(from, &data, &mut server_opener), Ok(Packet::Chat { text: "hello".to_string() }));
+
+    //the same packet again is a replay
+    assert_eq!(Packet::open(from, &data, &mut server_opener), Err(SecureError::Replayed));
+
+    //claiming to be another client breaks the authentication
+    let sealed = Packet::Ping.seal(id, &mut client_sealer);
+    let Ok((_, Packet::Sealed { data })) = Packet::decode(&sealed) else { panic!("not a sealed packet") };
+    assert_eq!(Packet::open(ClientId(id.0 ^ 1), &data, &mut server_opener), Err(SecureError::Invalid));
+
+    //and so does flipping a bit
+    let mut tampered = data.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert_eq!(Packet::open(id, &tampered, &mut server_opener), Err(SecureError::Invalid));
+    assert_eq!(Packet::open(id, &data, &mut server_opener), Ok(Packet::Ping));
+
+    //replies are sealed under the other key
+    let reply = ServerPacket::seal("alice : hi", &mut server_sealer);
+    let Ok(ServerPacket::Sealed { data }) = ServerPacket::decode(&reply) else { panic!("not a sealed reply") };
+    assert_eq!(ServerPacket::open(&data, &mut client_opener), Ok("alice : hi".to_string()));
+    assert!(Packet::open(id, &data, &mut server_opener).is_err());
+}
@@ -0,0 +1,288 @@
+use lib::limit::{Limiter, Limits, Rate, Verdict};
+use lib::reliable::{Endpoint, Reliability};
+use lib::Transport;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+// a udp socket that loses, delays and duplicates what it sends, driven by a seeded generator
+// so a failing run can be repeated
+struct Lossy {
+    socket: Arc<UdpSocket>,
+    rng: Mutex<u64>,
+    //percentages
+    drop: u64,
+    duplicate: u64,
+    //sends are held back up to this long, which reorders them
+    max_delay_ms: u64,
+}
+
+impl Lossy {
+    async fn bind(seed: u64, drop: u64, duplicate: u64, max_delay_ms: u64) -> Lossy {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        Lossy { socket: Arc::new(socket), rng: Mutex::new(seed), drop, duplicate, max_delay_ms }
+    }
+
+    fn addr(&self) -> SocketAddr {
+        self.socket.local_addr().unwrap()
+    }
+
+    fn next(&self, below: u64) -> u64 {
+        let mut state = self.rng.lock().unwrap();
+        *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (*state >> 33) % below.max(1)
+    }
+}
+
+impl Transport for Lossy {
+    async fn send_to(&self, datagram: &[u8], to: SocketAddr) -> io::Result<usize> {
+        if self.next(100) < self.drop {
+            return Ok(datagram.len());
+        }
+        let copies = if self.next(100) < self.duplicate { 2 } else { 1 };
+        for _ in 0..copies {
+            let delay = Duration::from_millis(self.next(self.max_delay_ms + 1));
+            let socket = self.socket.clone();
+            let datagram = datagram.to_vec();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                let _ = socket.send_to(&datagram, to).await;
+            });
+        }
+        Ok(datagram.len())
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.socket.recv_from(buf).await
+    }
+}
+
+fn fast() -> Reliability {
+    Reliability { retransmit_after: Duration::from_millis(30), max_retries: 30, ..Reliability::default() }
+}
+
+async fn receive(deliveries: &mut UnboundedReceiver<(SocketAddr, Vec<u8>)>, count: usize) -> Vec<(SocketAddr, String)> {
+    let mut received = Vec::new();
+    while received.len() < count {
+        let (from, payload) = tokio::time::timeout(TIMEOUT, deliveries.recv())
+            .await
+            .expect("timed out waiting for deliveries")
+            .expect("endpoint stopped");
+        received.push((from, String::from_utf8(payload).unwrap()));
+    }
+    received
+}
+
+fn numbered(prefix: &str, count: usize) -> Vec<String> {
+    (0..count).map(|i| format!("{} {}", prefix, i)).collect()
+}
+
+#[tokio::test]
+async fn lossy_links_deliver_in_order() {
+    let alice = Lossy::bind(1, 30, 0, 20).await;
+    let bob = Lossy::bind(2, 30, 0, 20).await;
+    let (alice_addr, bob_addr) = (alice.addr(), bob.addr());
+    let (alice, mut alice_rx) = Endpoint::start(alice, fast());
+    let (bob, mut bob_rx) = Endpoint::start(bob, fast());
+
+    let to_bob = numbered("to bob", 100);
+    let to_alice = numbered("to alice", 100);
+    for (a, b) in to_bob.iter().zip(&to_alice) {
+        alice.send(bob_addr, a.as_bytes()).await.unwrap();
+        bob.send(alice_addr, b.as_bytes()).await.unwrap();
+    }
+
+    let at_bob = receive(&mut bob_rx, 100).await;
+    let at_alice = receive(&mut alice_rx, 100).await;
+    assert!(at_bob.iter().all(|(from, _)| *from == alice_addr));
+    assert!(at_alice.iter().all(|(from, _)| *from == bob_addr));
+    assert_eq!(at_bob.into_iter().map(|(_, text)| text).collect::<Vec<_>>(), to_bob);
+    assert_eq!(at_alice.into_iter().map(|(_, text)| text).collect::<Vec<_>>(), to_alice);
+}
+
+#[tokio::test]
+async fn duplicates_are_delivered_once() {
+    let sender = Lossy::bind(3, 0, 50, 10).await;
+    let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let receiver_addr = receiver.local_addr().unwrap();
+    let (sender, _sender_rx) = Endpoint::start(sender, fast());
+    let (_receiver, mut receiver_rx) = Endpoint::start(receiver, fast());
+
+    let sent = numbered("line", 50);
+    for text in &sent {
+        sender.send(receiver_addr, text.as_bytes()).await.unwrap();
+    }
+    let received = receive(&mut receiver_rx, 50).await;
+    assert_eq!(received.into_iter().map(|(_, text)| text).collect::<Vec<_>>(), sent);
+
+    //nothing else trickles in once the retransmissions have settled
+    let extra = tokio::time::timeout(Duration::from_millis(500), receiver_rx.recv()).await;
+    assert!(extra.is_err(), "got an extra delivery: {:?}", extra);
+}
+
+#[tokio::test]
+async fn senders_are_ordered_independently() {
+    let receiver = Lossy::bind(4, 20, 10, 20).await;
+    let receiver_addr = receiver.addr();
+    let (_receiver, mut receiver_rx) = Endpoint::start(receiver, fast());
+
+    let mut senders = Vec::new();
+    for seed in 5..8 {
+        let socket = Lossy::bind(seed, 20, 10, 20).await;
+        let addr = socket.addr();
+        let (endpoint, rx) = Endpoint::start(socket, fast());
+        senders.push((addr, endpoint, rx));
+    }
+    for i in 0..30 {
+        for (_, endpoint, _) in &senders {
+            endpoint.send(receiver_addr, format!("line {}", i).as_bytes()).await.unwrap();
+        }
+    }
+
+    let received = receive(&mut receiver_rx, 90).await;
+    for (addr, _, _) in &senders {
+        let from_sender: Vec<String> = received.iter().filter(|(from, _)| from == addr).map(|(_, text)| text.clone()).collect();
+        assert_eq!(from_sender, numbered("line", 30));
+    }
+}
+
+#[tokio::test]
+async fn large_payloads_are_fragmented_and_reassembled() {
+    let config = Reliability { mtu: 200, ..fast() };
+    let sender = Lossy::bind(8, 20, 10, 20).await;
+    let receiver = Lossy::bind(9, 20, 10, 20).await;
+    let receiver_addr = receiver.addr();
+    let (sender, _sender_rx) = Endpoint::start(sender, config);
+    let (_receiver, mut receiver_rx) = Endpoint::start(receiver, config);
+
+    let long = (0..50_000).map(|i| (b'a' + (i % 26) as u8) as char).collect::<String>();
+    let sent = vec!["short".to_string(), long.clone(), String::new(), long];
+    for text in &sent {
+        sender.send(receiver_addr, text.as_bytes()).await.unwrap();
+    }
+    let received = receive(&mut receiver_rx, sent.len()).await;
+    assert_eq!(received.into_iter().map(|(_, text)| text).collect::<Vec<_>>(), sent);
+}
+
+#[tokio::test]
+async fn oversized_payloads_are_refused() {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let (endpoint, _rx) = Endpoint::start(socket, Reliability::default());
+    let err = endpoint.send(addr, &vec![0; lib::MAX_MESSAGE + 1]).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn idle_and_forgotten_peers_are_dropped() {
+    let config = Reliability { idle_timeout: Duration::from_millis(300), ..fast() };
+    let alice = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let bob = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let (alice_addr, bob_addr) = (alice.local_addr().unwrap(), bob.local_addr().unwrap());
+    let (alice, _alice_rx) = Endpoint::start(alice, config);
+    let (bob, mut bob_rx) = Endpoint::start(bob, config);
+
+    alice.send(bob_addr, b"hello").await.unwrap();
+    receive(&mut bob_rx, 1).await;
+    alice.flush(bob_addr).await;
+    assert_eq!((alice.peers(), bob.peers()), (1, 1));
+
+    //forgetting is immediate, and a peer that didn't know it was forgotten still gets through afterwards
+    bob.forget(alice_addr);
+    assert_eq!(bob.peers(), 0);
+    alice.send(bob_addr, b"still there").await.unwrap();
+    assert_eq!(receive(&mut bob_rx, 1).await[0].1, "still there");
+    alice.flush(bob_addr).await;
+
+    //idle peers go once the timeout is up
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!((alice.peers(), bob.peers()), (0, 0));
+
+    //and a forgotten peer is picked up again as a new one
+    alice.send(bob_addr, b"again").await.unwrap();
+    assert_eq!(receive(&mut bob_rx, 1).await[0].1, "again");
+}
+
+#[tokio::test]
+async fn refused_senders_leave_no_trace() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let receiver_addr = receiver.local_addr().unwrap();
+    let (receiver, mut receiver_rx) = Endpoint::start_guarded(receiver, fast(), Arc::new(|_| false));
+    let (sender, _sender_rx) = Endpoint::start(UdpSocket::bind("127.0.0.1:0").await.unwrap(), fast());
+
+    for text in numbered("flood", 20) {
+        sender.send(receiver_addr, text.as_bytes()).await.unwrap();
+    }
+    //nothing is delivered, nothing is acked and nothing is kept about the sender
+    assert!(tokio::time::timeout(Duration::from_millis(500), receiver_rx.recv()).await.is_err());
+    assert!(tokio::time::timeout(Duration::from_millis(200), sender.flush(receiver_addr)).await.is_err());
+    assert_eq!(receiver.peers(), 0);
+}
+
+#[tokio::test]
+async fn floods_are_cut_off_per_datagram() {
+    let limits = Limits { per_address: Rate { per_second: 0.001, burst: 5.0 }, ban_after: 3, ..Limits::default() };
+    let limiter = Arc::new(Mutex::new(Limiter::new(limits)));
+    let admit = {
+        let limiter = limiter.clone();
+        Arc::new(move |from: SocketAddr| limiter.lock().unwrap().check_address(from.ip()) == Verdict::Pass)
+    };
+    let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let receiver_addr = receiver.local_addr().unwrap();
+    let (_receiver, mut receiver_rx) = Endpoint::start_guarded(receiver, fast(), admit);
+    let config = Reliability { mtu: 200, ..fast() };
+    let (sender, _sender_rx) = Endpoint::start(UdpSocket::bind("127.0.0.1:0").await.unwrap(), config);
+
+    //two short payloads take a datagram each, the long one doesn't get all of its fragments in
+    sender.send(receiver_addr, b"one").await.unwrap();
+    sender.send(receiver_addr, b"two").await.unwrap();
+    sender.send(receiver_addr, &[b'x'; 2000]).await.unwrap();
+    let received = receive(&mut receiver_rx, 2).await;
+    assert_eq!(received.into_iter().map(|(_, text)| text).collect::<Vec<_>>(), ["one", "two"]);
+    assert!(tokio::time::timeout(Duration::from_millis(500), receiver_rx.recv()).await.is_err());
+
+    //the retransmissions keep it over the limit until it is banned
+    let dropped = limiter.lock().unwrap().dropped();
+    assert!(dropped.address >= 3 && dropped.banned > 0, "{:?}", dropped);
+}
+
+#[tokio::test]
+async fn forged_epochs_dont_cut_off_the_real_sender() {
+    let alice = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let bob = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let bob_addr = bob.local_addr().unwrap();
+    let (alice, _alice_rx) = Endpoint::start(alice, fast());
+    let (_bob, mut bob_rx) = Endpoint::start(bob, fast());
+
+    alice.send(bob_addr, b"before").await.unwrap();
+    assert_eq!(receive(&mut bob_rx, 1).await[0].1, "before");
+
+    //data datagrams from alice's own address, each in an epoch of its own starting over at seq 1
+    for epoch in 0..10u32 {
+        let mut forged = vec![0];
+        forged.extend_from_slice(&epoch.to_be_bytes());
+        forged.extend_from_slice(&1u64.to_be_bytes());
+        forged.extend_from_slice(&1u64.to_be_bytes());
+        forged.extend_from_slice(b"junk");
+        alice.transport().send_to(&forged, bob_addr).await.unwrap();
+    }
+
+    //alice carries on in the epoch she has been using
+    let sent = numbered("after", 5);
+    for text in &sent {
+        alice.send(bob_addr, text.as_bytes()).await.unwrap();
+    }
+    let received = tokio::time::timeout(Duration::from_secs(2), receive(&mut bob_rx, 5)).await.expect("alice was cut off");
+    assert_eq!(received.into_iter().map(|(_, text)| text).collect::<Vec<_>>(), sent);
+
+    //and when she really starts over, that gets through right away too
+    alice.forget(bob_addr);
+    alice.send(bob_addr, b"restarted").await.unwrap();
+    assert_eq!(receive(&mut bob_rx, 1).await[0].1, "restarted");
+    assert!(tokio::time::timeout(Duration::from_millis(300), bob_rx.recv()).await.is_err());
+}
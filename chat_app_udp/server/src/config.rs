@@ -0,0 +1,221 @@
+use clap::{value_parser, Arg, Command};
+use lib::discovery::DEFAULT_GROUP;
+use lib::limit::{Limits, Rate};
+use lib::reliable::{Reliability, MIN_MTU};
+use lib::MAX_DATAGRAM;
+use std::ffi::OsString;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::Duration;
+
+//server settings, every flag can also be given through its environment variable
+pub struct Config {
+    //address the server binds to
+    pub bind: String,
+    //clients that joined, further ones are turned away
+    pub max_clients: usize,
+    //silence after which a client is dropped
+    pub client_timeout: Duration,
+    pub reliability: Reliability,
+    //what the server announces itself as, and where. no announcements without an interval
+    pub name: String,
+    pub multicast: SocketAddrV4,
+    pub multicast_if: Ipv4Addr,
+    pub announce_every: Option<Duration>,
+    pub limits: Limits,
+    //env_logger filter, e.g. "info" or "server=debug"
+    pub log_level: String,
+}
+
+impl Config {
+    //parsing the process arguments
+    pub fn load() -> Config {
+        Config::from_args(std::env::args_os())
+    }
+
+    //parsing an explicit argument list, the first item is the program name
+    pub fn from_args<I, T>(args: I) -> Config
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = command().get_matches_from(args);
+        let rate = |key: &str| Rate {
+            per_second: (*matches.get_one::<u32>(&format!("{}-rate", key)).unwrap()).into(),
+            burst: (*matches.get_one::<u32>(&format!("{}-burst", key)).unwrap()).into(),
+        };
+        let every = *matches.get_one::<u64>("announce-every").unwrap();
+        Config {
+            bind: matches.get_one::<String>("bind").unwrap().clone(),
+            max_clients: *matches.get_one::<usize>("max-clients").unwrap(),
+            client_timeout: Duration::from_secs(*matches.get_one::<u64>("client-timeout").unwrap()),
+            reliability: Reliability { mtu: *matches.get_one::<u64>("mtu").unwrap() as usize, ..Reliability::default() },
+            name: matches.get_one::<String>("name").unwrap().clone(),
+            multicast: *matches.get_one::<SocketAddrV4>("multicast").unwrap(),
+            multicast_if: *matches.get_one::<Ipv4Addr>("multicast-if").unwrap(),
+            announce_every: (every > 0).then(|| Duration::from_secs(every)),
+            limits: Limits {
+                per_address: rate("address"),
+                per_client: rate("client"),
+                global: rate("global"),
+                ban_after: *matches.get_one::<u32>("ban-after").unwrap(),
+                ban_for: Duration::from_secs(*matches.get_one::<u64>("ban-for").unwrap()),
+            },
+            log_level: matches.get_one::<String>("log-level").unwrap().clone(),
+        }
+    }
+}
+
+fn command() -> Command {
+    Command::new("udp chat server")
+        .about("udp chat server")
+        .arg(
+            Arg::new("bind")
+                .long("bind")
+                .env("CHAT_BIND")
+                .value_name("ADDR")
+                .help("address to listen on")
+                .default_value("127.0.0.1:8080"),
+        )
+        .arg(
+            Arg::new("max-clients")
+                .long("max-clients")
+                .env("CHAT_MAX_CLIENTS")
+                .value_name("COUNT")
+                .help("maximum number of joined clients")
+                .value_parser(value_parser!(usize))
+                .default_value("1024"),
+        )
+        .arg(
+            Arg::new("client-timeout")
+                .long("client-timeout")
+                .env("CHAT_CLIENT_TIMEOUT")
+                .value_name("SECS")
+                .help("seconds of silence after which a client is dropped")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("30"),
+        )
+        .arg(
+            Arg::new("mtu")
+                .long("mtu")
+                .env("CHAT_MTU")
+                .value_name("BYTES")
+                .help("largest datagram to send, longer messages are split into fragments")
+                .value_parser(value_parser!(u64).range(MIN_MTU as u64..=MAX_DATAGRAM as u64))
+                .default_value("1200"),
+        )
+        .arg(
+            Arg::new("name")
+                .long("name")
+                .env("CHAT_SERVER_NAME")
+                .value_name("NAME")
+                .help("name the server announces itself under")
+                .default_value("udp chat"),
+        )
+        .arg(
+            Arg::new("multicast")
+                .long("multicast")
+                .env("CHAT_MULTICAST")
+                .value_name("GROUP:PORT")
+                .help("multicast group on which the server announces itself")
+                .value_parser(value_parser!(SocketAddrV4))
+                .default_value(DEFAULT_GROUP),
+        )
+        .arg(
+            Arg::new("multicast-if")
+                .long("multicast-if")
+                .env("CHAT_MULTICAST_IF")
+                .value_name("ADDR")
+                .help("address of the interface to announce on")
+                .value_parser(value_parser!(Ipv4Addr))
+                .default_value("0.0.0.0"),
+        )
+        .arg(
+            Arg::new("announce-every")
+                .long("announce-every")
+                .env("CHAT_ANNOUNCE_EVERY")
+                .value_name("SECS")
+                .help("seconds between announcements, 0 turns them off")
+                .value_parser(value_parser!(u64))
+                .default_value("2"),
+        )
+        .arg(
+            Arg::new("address-rate")
+                .long("address-rate")
+                .env("CHAT_ADDRESS_RATE")
+                .value_name("PACKETS")
+                .help("datagrams a second one address may send on average")
+                .value_parser(value_parser!(u32).range(1..))
+                .default_value("500"),
+        )
+        .arg(
+            Arg::new("address-burst")
+                .long("address-burst")
+                .env("CHAT_ADDRESS_BURST")
+                .value_name("PACKETS")
+                .help("datagrams one address may send at once")
+                .value_parser(value_parser!(u32).range(1..))
+                .default_value("1000"),
+        )
+        .arg(
+            Arg::new("client-rate")
+                .long("client-rate")
+                .env("CHAT_CLIENT_RATE")
+                .value_name("PACKETS")
+                .help("packets a second one client may send on average")
+                .value_parser(value_parser!(u32).range(1..))
+                .default_value("10"),
+        )
+        .arg(
+            Arg::new("client-burst")
+                .long("client-burst")
+                .env("CHAT_CLIENT_BURST")
+                .value_name("PACKETS")
+                .help("packets one client may send at once")
+                .value_parser(value_parser!(u32).range(1..))
+                .default_value("20"),
+        )
+        .arg(
+            Arg::new("global-rate")
+                .long("global-rate")
+                .env("CHAT_GLOBAL_RATE")
+                .value_name("PACKETS")
+                .help("datagrams a second all senders together may send on average")
+                .value_parser(value_parser!(u32).range(1..))
+                .default_value("20000"),
+        )
+        .arg(
+            Arg::new("global-burst")
+                .long("global-burst")
+                .env("CHAT_GLOBAL_BURST")
+                .value_name("PACKETS")
+                .help("datagrams all senders together may send at once")
+                .value_parser(value_parser!(u32).range(1..))
+                .default_value("40000"),
+        )
+        .arg(
+            Arg::new("ban-after")
+                .long("ban-after")
+                .env("CHAT_BAN_AFTER")
+                .value_name("PACKETS")
+                .help("packets over its limit within ten seconds after which a sender is banned, 0 never bans")
+                .value_parser(value_parser!(u32))
+                .default_value("50"),
+        )
+        .arg(
+            Arg::new("ban-for")
+                .long("ban-for")
+                .env("CHAT_BAN_FOR")
+                .value_name("SECS")
+                .help("seconds a banned sender is ignored for")
+                .value_parser(value_parser!(u64).range(1..=86_400))
+                .default_value("60"),
+        )
+        .arg(
+            Arg::new("log-level")
+                .long("log-level")
+                .env("CHAT_LOG_LEVEL")
+                .value_name("FILTER")
+                .help("log filter, e.g. warn, info or debug")
+                .default_value("info"),
+        )
+}
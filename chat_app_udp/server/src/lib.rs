@@ -0,0 +1,361 @@
+use lib::discovery::{announce, multicast_socket, Announcement, Role};
+use lib::limit::{Dropped, Limiter, Verdict};
+use lib::packet::{ClientId, Packet, ServerPacket};
+use lib::reliable::Endpoint;
+use lib::secure::{Handshake, Opener, Sealer};
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub mod config;
+pub use config::Config;
+
+//lines waiting to go out to one client, anything beyond is dropped for that client only
+const QUEUE_LEN: usize = 100;
+//how often the dropped packet counters are logged, when they changed
+const STATS_EVERY: Duration = Duration::from_secs(60);
+//handshakes that haven't led to a join yet, in all and from one address. a hello costs nothing to send
+//and anyone can put any address on it, so once either is full the oldest one makes room
+const PENDING_HANDSHAKES: usize = 256;
+const PENDING_PER_ADDRESS: usize = 8;
+//how long a client has from its hello to its join
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+//serving clients on `socket` for as long as the process runs
+pub async fn run(socket: UdpSocket, config: Config) {
+    let Config { max_clients, client_timeout, reliability, limits, .. } = config;
+    let local = socket.local_addr().expect("Failed to get local address");
+    //every datagram is acked and retransmitted until it is, and handed over in order per client
+    //flood protection, every data datagram has to get past it before the endpoint keeps or acks anything for it
+    let limiter = Arc::new(Mutex::new(Limiter::new(limits)));
+    let admit = {
+        let limiter = limiter.clone();
+        Arc::new(move |from: SocketAddr| passes(limiter.lock().unwrap().check_address(from.ip()), from.ip(), limits.ban_for))
+    };
+    let (endpoint, mut deliveries) = Endpoint::start_guarded(socket, reliability, admit);
+
+    //letting clients on the local network find us, the port is all they need besides where the announcement came from
+    if let Some(every) = config.announce_every {
+        let (group, interface) = (config.multicast, config.multicast_if);
+        let announcement = Announcement { role: Role::Server, id: rand::random(), port: local.port(), name: config.name };
+        //clients take the address an announcement comes from, which is never loopback unless the interface is
+        if local.ip().is_loopback() && !interface.is_loopback() {
+            log::warn!("Not announcing on {}, clients elsewhere can't reach {}. bind to another address or use --multicast-if 127.0.0.1", group, local);
+        } else {
+            match multicast_socket(group, interface) {
+                Ok(socket) => {
+                    tokio::spawn(async move {
+                        if let Err(err) = announce(&socket, group, &announcement, every).await {
+                            log::warn!("Stopped announcing on {}: {}", group, err);
+                        }
+                    });
+                }
+                Err(err) => log::warn!("Not announcing on {}: {}", group, err),
+            }
+        }
+    }
+
+    //creating a shared state for managing connected clients using a mutex protected hashmap to avoid data race and safely sharing between channels
+    let clients: Clients = Arc::new(Mutex::new(HashMap::new()));
+    //and one for the encryption keys, which exist from the client's hello on
+    let sessions: Sessions = Arc::new(Mutex::new(HashMap::new()));
+
+    //creating an unbounded channel for receiving messages from all clients
+    let (tx,rx) = mpsc::unbounded_channel::<(ClientId, Packet, SocketAddr, Option<Sealer>)>();
+
+    //spawning a task to handle incoming messages
+    tokio::spawn(handle_messages(clients.clone(), sessions.clone(), endpoint.clone(), max_clients, rx));
+
+    //spawning a task that drops clients which went quiet
+    tokio::spawn(evict_silent(clients.clone(), sessions.clone(), endpoint.clone(), client_timeout));
+
+    //spawning a task that logs what the limiter dropped
+    tokio::spawn(report_dropped(limiter.clone()));
+
+    while let Some((addr, payload)) = deliveries.recv().await {
+        let (id, packet) = match Packet::decode(&payload) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                log::debug!("Ignoring datagram from {}: {}", addr, err);
+                continue;
+            }
+        };
+
+        match packet {
+            Packet::Hello { key } => {
+                if ClientId::of(&key) != id {
+                    log::debug!("Ignoring hello from {}, the key doesn't match id {}", addr, id);
+                    continue;
+                }
+                //a repeated hello gets the same answer, the session it already has stays as it is
+                let full = clients.lock().unwrap().len() >= max_clients;
+                let reply = {
+                    let mut sessions = sessions.lock().unwrap();
+                    match sessions.get(&id) {
+                        Some(session) => Some(session.server_key),
+                        //new clients are turned away once the server is full
+                        None if full => None,
+                        None => {
+                            make_room(&mut sessions, addr);
+                            let handshake = Handshake::new();
+                            let server_key = handshake.public_key();
+                            let (sealer, opener) = handshake.finish(key, false);
+                            sessions.insert(id, Session { server_key, opener, sealer: Some(sealer), addr, started: Instant::now() });
+                            Some(server_key)
+                        }
+                    }
+                };
+                let reply = match reply {
+                    Some(key) => ServerPacket::Hello { key },
+                    None => {
+                        log::warn!("Ignoring {} at {}, server is full", id, addr);
+                        ServerPacket::Full
+                    }
+                };
+                if let Err(err) = endpoint.send(addr, &reply.encode()).await {
+                    log::warn!("Failed to answer hello from {}: {}", addr, err);
+                }
+            }
+            Packet::Sealed { data } => {
+                let opened = {
+                    let mut sessions = sessions.lock().unwrap();
+                    match sessions.get_mut(&id) {
+                        Some(session) => Packet::open(id, &data, &mut session.opener).map(|packet| {
+                            //the joining client's send task takes over sealing for it
+                            let sealer = if let Packet::Join { .. } = packet { session.sealer.take() } else { None };
+                            (packet, sealer)
+                        }).map_err(Some),
+                        None => Err(None),
+                    }
+                };
+                match opened {
+                    //only now is it known the packet really comes from the client it says
+                    Ok(_) if !passes(limiter.lock().unwrap().check_client(id), id, limits.ban_for) => continue,
+                    //trying to send the message, but handle backpressure gracefully
+                    Ok((packet, sealer)) => {
+                        if let Err(err) = tx.send((id, packet, addr, sealer)) {
+                            log::error!("Failed to send message: {:#?}", err);
+                        }
+                    }
+                    Err(Some(err)) => log::debug!("Ignoring packet from {} at {}: {}", id, addr, err),
+                    //e.g. it was evicted after a network hiccup, telling it so it can join again
+                    Err(None) => {
+                        log::debug!("No session for {} at {}", id, addr);
+                        if let Err(err) = endpoint.send(addr, &ServerPacket::NoSession { id }.encode()).await {
+                            log::warn!("Failed to answer {}: {}", addr, err);
+                        }
+                    }
+                }
+            }
+            _ => log::debug!("Ignoring unencrypted packet from {} at {}", id, addr),
+        }
+    }
+}
+
+//function for telling whether a packet gets past the limiter, and logging the sender if it just got banned
+fn passes(verdict: Verdict, sender: impl std::fmt::Display, ban_for: Duration) -> bool {
+    match verdict {
+        Verdict::Pass => true,
+        Verdict::Drop => false,
+        Verdict::Ban => {
+            log::warn!("Banning {} for {} seconds, it keeps going over its rate limit", sender, ban_for.as_secs());
+            false
+        }
+    }
+}
+
+//function for logging the dropped packet counters every so often, when they changed, and forgetting
+//senders the limiter no longer needs to track
+async fn report_dropped(limiter: Arc<Mutex<Limiter>>) {
+    let mut reported = Dropped::default();
+    let mut tick = tokio::time::interval(STATS_EVERY);
+    loop {
+        tick.tick().await;
+        let mut limiter = limiter.lock().unwrap();
+        let dropped = limiter.dropped();
+        if dropped != reported {
+            log::info!("Dropped {} packets so far: {}", dropped.total(), dropped);
+            reported = dropped;
+        }
+        limiter.prune();
+    }
+}
+
+// a finished key exchange, kept from the client's hello until it leaves or goes quiet
+struct Session {
+    //sent again if the client repeats its hello
+    server_key: [u8; 32],
+    opener: Opener,
+    //handed to the client's send task once it joins, until then the handshake is pending
+    sealer: Option<Sealer>,
+    //where the hello came from
+    addr: SocketAddr,
+    started: Instant,
+}
+
+impl Session {
+    fn is_pending(&self) -> bool {
+        self.sealer.is_some()
+    }
+}
+
+type Sessions = Arc<Mutex<HashMap<ClientId, Session>>>;
+
+//function for dropping the oldest pending handshake from `addr`, or from anywhere, when there is no room for another one
+fn make_room(sessions: &mut HashMap<ClientId, Session>, addr: SocketAddr) {
+    for (same_addr, limit) in [(true, PENDING_PER_ADDRESS), (false, PENDING_HANDSHAKES)] {
+        let pending: Vec<(ClientId, Instant)> = sessions
+            .iter()
+            .filter(|(_, session)| session.is_pending() && (!same_addr || session.addr.ip() == addr.ip()))
+            .map(|(id, session)| (*id, session.started))
+            .collect();
+        if pending.len() >= limit {
+            if let Some((id, _)) = pending.into_iter().min_by_key(|(_, started)| *started) {
+                log::debug!("Dropping the pending handshake of {}, too many handshakes going on", id);
+                sessions.remove(&id);
+            }
+        }
+    }
+}
+
+//a client that joined, keyed by its id in the clients map
+struct Client {
+    name: String,
+    //where its datagrams last came from, which is where everything for it goes
+    addr: watch::Sender<SocketAddr>,
+    //when anything was last heard from it, heartbeats included
+    last_seen: Instant,
+    //lines for its send task, dropping the client ends the task once the queue is drained
+    outbound: mpsc::Sender<Arc<str>>,
+}
+
+impl Client {
+    //queueing a line without waiting, a client that can't keep up just misses it
+    fn queue(&self, msg: &Arc<str>) {
+        if let Err(err) = self.outbound.try_send(msg.clone()) {
+            log::warn!("Dropping message for {}: {}", self.name, err);
+        }
+    }
+}
+
+//the table is only ever locked briefly and never across an await, sending happens in the per-client tasks.
+//whoever needs both locks takes the clients first
+type Clients = Arc<Mutex<HashMap<ClientId, Client>>>;
+
+//function for handling incoming messages from clients
+async fn handle_messages(
+    clients: Clients,
+    sessions: Sessions,
+    endpoint: Endpoint<UdpSocket>,
+    max_clients: usize,
+    mut rx: mpsc::UnboundedReceiver<(ClientId, Packet, SocketAddr, Option<Sealer>)>,
+) {
+    //matching pattern for received messages
+    while let Some((id, packet, addr, sealer)) = rx.recv().await {
+        log::debug!("Received {:?} from {} at {}", packet, id, addr);
+
+        //obtaining a lock on the clients hashmap
+        let mut clients = clients.lock().unwrap();
+        if let Some(client) = clients.get_mut(&id) {
+            client.last_seen = Instant::now();
+            //e.g. a nat rebinding, replies follow the client to its new address
+            if *client.addr.borrow() != addr {
+                log::info!("{} moved from {} to {}", client.name, *client.addr.borrow(), addr);
+                client.addr.send_replace(addr);
+            }
+        }
+
+        match packet {
+            Packet::Join { name } => {
+                //only the first join comes with the sealer
+                let Some(sealer) = sealer.filter(|_| !clients.contains_key(&id)) else {
+                    continue;
+                };
+                //others may have joined since this client's hello
+                if clients.len() >= max_clients {
+                    log::warn!("Turning away {} at {}, server is full", id, addr);
+                    sessions.lock().unwrap().remove(&id);
+                    let endpoint = endpoint.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = endpoint.send(addr, &ServerPacket::Full.encode()).await {
+                            log::warn!("Failed to answer {}: {}", addr, err);
+                        }
+                    });
+                    continue;
+                }
+                log::info!("{} joined as {} from {}", name, id, addr);
+                let (outbound, queue) = mpsc::channel(QUEUE_LEN);
+                let (addr, addr_rx) = watch::channel(addr);
+                tokio::spawn(send_to_client(endpoint.clone(), addr_rx, sealer, queue));
+                //The key is the client's id, and the value is what we know about that client.
+                clients.insert(id, Client { name: name.clone(), addr, last_seen: Instant::now(), outbound });
+                broadcast(&clients, id, &format!("{} has joined the chat", name));
+            }
+            Packet::Leave => {
+                sessions.lock().unwrap().remove(&id);
+                if let Some(client) = clients.remove(&id) {
+                    log::info!("{} left", client.name);
+                    endpoint.forget(*client.addr.borrow());
+                    broadcast(&clients, id, &format!("{} has left the chat", client.name));
+                }
+            }
+            Packet::Ping | Packet::Hello { .. } | Packet::Sealed { .. } => {}
+            Packet::Chat { text } => match clients.get(&id) {
+                Some(client) => {
+                    let msg = format!("{} : {}", client.name, text);
+                    broadcast(&clients, id, &msg);
+                }
+                None => log::debug!("Ignoring chat from {} at {}, it never joined", id, addr),
+            },
+        }
+    }
+}
+
+//queueing a line for every joined client except the one it came from
+fn broadcast(clients: &HashMap<ClientId, Client>, sender: ClientId, msg: &str) {
+    let msg: Arc<str> = msg.into();
+    for (id, client) in clients.iter() {
+        if *id != sender {
+            client.queue(&msg);
+        }
+    }
+}
+
+//sealing and sending one client's lines in order, a slow or unreachable client only holds up its own queue
+async fn send_to_client(endpoint: Endpoint<UdpSocket>, addr: watch::Receiver<SocketAddr>, mut sealer: Sealer, mut queue: mpsc::Receiver<Arc<str>>) {
+    while let Some(msg) = queue.recv().await {
+        let to = *addr.borrow();
+        if let Err(err) = endpoint.send(to, &ServerPacket::seal(&msg, &mut sealer)).await {
+            log::warn!("Failed to send message to {}: {:?}", to, err);
+        }
+    }
+}
+
+//checking every so often for clients that haven't been heard from within `timeout`,
+//they are removed and everyone else is told they left. handshakes that never led to a join go too
+async fn evict_silent(clients: Clients, sessions: Sessions, endpoint: Endpoint<UdpSocket>, timeout: Duration) {
+    let mut tick = tokio::time::interval((timeout / 4).max(Duration::from_millis(100)));
+    loop {
+        tick.tick().await;
+        let mut clients = clients.lock().unwrap();
+        let mut sessions = sessions.lock().unwrap();
+        sessions.retain(|id, session| clients.contains_key(id) || session.started.elapsed() <= HANDSHAKE_TIMEOUT);
+        let silent: Vec<ClientId> = clients
+            .iter()
+            .filter(|(_, client)| client.last_seen.elapsed() > timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in silent {
+            sessions.remove(&id);
+            if let Some(client) = clients.remove(&id) {
+                log::info!("{} at {} timed out", client.name, *client.addr.borrow());
+                endpoint.forget(*client.addr.borrow());
+                broadcast(&clients, id, &format!("{} has left the chat (timed out)", client.name));
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use lib::packet::{ClientId, Packet, ServerPacket};
+use lib::reliable::{Endpoint, Reliability};
+use lib::secure::{Handshake, Opener, Sealer};
+use server::{run, Config};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+const RECV_TIMEOUT: Duration = Duration::from_secs(10);
+
+//a server on a free local port, announcing nothing, with extra flags on top
+async fn start_server(flags: &[&str]) -> SocketAddr {
+    let mut args = vec!["server", "--announce-every", "0"];
+    args.extend_from_slice(flags);
+    let config = Config::from_args(args);
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(run(socket, config));
+    addr
+}
+
+//a scripted client speaking the packet protocol directly
+struct TestClient {
+    endpoint: Endpoint<UdpSocket>,
+    deliveries: UnboundedReceiver<(SocketAddr, Vec<u8>)>,
+    server: SocketAddr,
+    id: ClientId,
+    sealer: Sealer,
+    opener: Opener,
+}
+
+impl TestClient {
+    //going through the key exchange, or what the server said instead of its hello
+    async fn connect(server: SocketAddr) -> Result<TestClient, ServerPacket> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (endpoint, mut deliveries) = Endpoint::start(socket, Reliability::default());
+        let handshake = Handshake::new();
+        let id = ClientId::of(&handshake.public_key());
+        endpoint.send(server, &Packet::Hello { key: handshake.public_key() }.encode(id)).await.unwrap();
+        let (_, payload) = tokio::time::timeout(RECV_TIMEOUT, deliveries.recv()).await.unwrap().unwrap();
+        let key = match ServerPacket::decode(&payload).unwrap() {
+            ServerPacket::Hello { key } => key,
+            other => return Err(other),
+        };
+        let (sealer, opener) = handshake.finish(key, true);
+        Ok(TestClient { endpoint, deliveries, server, id, sealer, opener })
+    }
+
+    //connecting and joining under `name`
+    async fn join(server: SocketAddr, name: &str) -> TestClient {
+        let mut client = TestClient::connect(server).await.expect("no hello from the server");
+        client.send(Packet::Join { name: name.to_string() }).await;
+        client
+    }
+
+    async fn send(&mut self, packet: Packet) {
+        let sealed = packet.seal(self.id, &mut self.sealer);
+        self.endpoint.send(self.server, &sealed).await.unwrap();
+    }
+
+    async fn chat(&mut self, text: &str) {
+        self.send(Packet::Chat { text: text.to_string() }).await;
+    }
+
+    //the next line from the server, or None if nothing came within `wait`
+    async fn try_recv(&mut self, wait: Duration) -> Option<String> {
+        loop {
+            let (_, payload) = tokio::time::timeout(wait, self.deliveries.recv()).await.ok()?.unwrap();
+            if let Ok(ServerPacket::Sealed { data }) = ServerPacket::decode(&payload) {
+                return Some(ServerPacket::open(&data, &mut self.opener).unwrap());
+            }
+        }
+    }
+
+    async fn recv(&mut self) -> String {
+        self.try_recv(RECV_TIMEOUT).await.expect("timed out waiting for a line")
+    }
+
+    //skipping lines until `wanted` arrives
+    async fn recv_until(&mut self, wanted: &str) {
+        while self.recv().await != wanted {}
+    }
+}
+
+#[tokio::test]
+async fn silent_clients_are_evicted() {
+    let server = start_server(&["--client-timeout", "1"]).await;
+    let mut alice = TestClient::join(server, "alice").await;
+    let _bob = TestClient::join(server, "bob").await;
+    alice.recv_until("bob has joined the chat").await;
+
+    //alice keeps up her heartbeat, bob stays quiet
+    let deadline = tokio::time::Instant::now() + RECV_TIMEOUT;
+    loop {
+        assert!(tokio::time::Instant::now() < deadline, "bob was never evicted");
+        match alice.try_recv(Duration::from_millis(300)).await {
+            Some(line) if line == "bob has left the chat (timed out)" => break,
+            Some(_) => {}
+            None => alice.send(Packet::Ping).await,
+        }
+    }
+}
+
+#[tokio::test]
+async fn a_dead_client_holds_up_nobody() {
+    let server = start_server(&["--client-rate", "1000", "--client-burst", "1000"]).await;
+    let mut alice = TestClient::join(server, "alice").await;
+    let bob = TestClient::join(server, "bob").await;
+    let mut carol = TestClient::join(server, "carol").await;
+    alice.recv_until("carol has joined the chat").await;
+
+    //bob's socket goes away without a word, his queue fills up and overflows
+    drop(bob);
+    let lines: Vec<String> = (0..300).map(|i| format!("line {}", i)).collect();
+    for line in &lines {
+        alice.chat(line).await;
+    }
+    for line in &lines {
+        assert_eq!(carol.recv().await, format!("alice : {}", line));
+    }
+}
+
+#[tokio::test]
+async fn a_flooding_client_is_cut_off_while_others_chat() {
+    let server = start_server(&["--client-rate", "10", "--client-burst", "20", "--ban-after", "50"]).await;
+    let mut mallory = TestClient::join(server, "mallory").await;
+    let mut alice = TestClient::join(server, "alice").await;
+    let mut bob = TestClient::join(server, "bob").await;
+    alice.recv_until("bob has joined the chat").await;
+
+    for i in 0..200 {
+        mallory.chat(&format!("spam {}", i)).await;
+    }
+    mallory.endpoint.flush(server).await;
+    alice.chat("still here").await;
+
+    //bob hears alice, and only as much of mallory as her burst allows
+    let mut spam = 0;
+    loop {
+        let line = bob.recv().await;
+        if line == "alice : still here" {
+            break;
+        }
+        if line.starts_with("mallory : ") {
+            spam += 1;
+        }
+    }
+    while bob.try_recv(Duration::from_millis(300)).await.is_some() {
+        spam += 1;
+    }
+    assert!((1..=30).contains(&spam), "{} lines of spam got through", spam);
+
+    //she is banned now, so even a single line doesn't make it
+    mallory.chat("sorry").await;
+    assert_eq!(bob.try_recv(Duration::from_millis(500)).await, None);
+}
+
+#[tokio::test]
+async fn hellos_alone_dont_fill_the_server() {
+    let server = start_server(&["--max-clients", "2"]).await;
+
+    //a flood of handshakes that never go anywhere
+    let (flood, _replies) = Endpoint::start(UdpSocket::bind("127.0.0.1:0").await.unwrap(), Reliability::default());
+    for _ in 0..500 {
+        let key = Handshake::new().public_key();
+        flood.send(server, &Packet::Hello { key }.encode(ClientId::of(&key))).await.unwrap();
+    }
+    flood.flush(server).await;
+
+    //real clients still get in, up to the limit
+    let mut alice = TestClient::join(server, "alice").await;
+    let mut bob = TestClient::join(server, "bob").await;
+    alice.recv_until("bob has joined the chat").await;
+    bob.chat("hi").await;
+    alice.recv_until("bob : hi").await;
+    assert_eq!(TestClient::connect(server).await.err(), Some(ServerPacket::Full));
+}